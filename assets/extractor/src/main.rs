@@ -2,22 +2,72 @@
 //! # extractor
 //!
 //! `extractor` is a part of crate `envvars`. This mini-application is used to
-//! drop a list of environment variables into stdout and does nothing else.
+//! drop a list of environment variables into a private file created by `envvars`
+//! (`ENVVARS_EXTRACTOR_OUTPUT`; stdout if it isn't defined) and does nothing else.
+//!
+//! Keys and values are sent as they are (without converting into UTF-8): bytes on
+//! unix and UTF-16 code units on Windows, so non-Unicode data isn't lost. See
+//! `protocol.rs` for the format of output.
+
+mod protocol;
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs::OpenOptions,
+    io::{self, Write},
+};
+
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+#[cfg(windows)]
+use std::os::windows::ffi::OsStrExt;
 
-use std::env;
+fn put_len(output: &mut Vec<u8>, len: usize) {
+    output.extend_from_slice(&(len as u32).to_le_bytes());
+}
 
-const KEY_VALUE_SEP: &str = include_str!("./keys/key_value_sep.txt");
-const PAIR_SEP: &str = include_str!("./keys/pair_sep.txt");
-const MARKER: &str = include_str!("./keys/marker.txt");
+#[cfg(unix)]
+fn put(output: &mut Vec<u8>, value: &OsStr) {
+    put_len(output, value.len());
+    output.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(windows)]
+fn put(output: &mut Vec<u8>, value: &OsStr) {
+    let wide = value.encode_wide().collect::<Vec<u16>>();
+    put_len(output, wide.len() * 2);
+    wide.iter()
+        .for_each(|unit| output.extend_from_slice(&unit.to_le_bytes()));
+}
 
 pub fn main() {
-    let mut output: String = String::new();
-    for (key, value) in env::vars() {
-        output = format!(
-            "{output}{:?}{KEY_VALUE_SEP}{:?}{PAIR_SEP}",
-            key.as_bytes(),
-            value.as_bytes()
-        );
+    let nonce = env::var_os(protocol::NONCE_ENVVAR).unwrap_or_default();
+    let vars = env::vars_os()
+        .filter(|(key, _)| key != protocol::NONCE_ENVVAR && key != protocol::OUTPUT_ENVVAR)
+        .collect::<Vec<_>>();
+    let mut payload: Vec<u8> = Vec::new();
+    payload.extend_from_slice(&protocol::VERSION.to_le_bytes());
+    put(&mut payload, &nonce);
+    put_len(&mut payload, vars.len());
+    for (key, value) in vars.iter() {
+        put(&mut payload, key);
+        put(&mut payload, value);
+    }
+    let mut output: Vec<u8> = Vec::with_capacity(payload.len() + protocol::MARKER.len() * 2 + 8);
+    output.extend_from_slice(protocol::MARKER);
+    output.extend_from_slice(&payload);
+    output.extend_from_slice(&protocol::checksum(&payload).to_le_bytes());
+    output.extend_from_slice(protocol::MARKER);
+    if let Some(path) = env::var_os(protocol::OUTPUT_ENVVAR) {
+        // The file is created by envvars; it's never created here. Output is appended:
+        // nested runs of the extractor don't overwrite output of each other.
+        if let Ok(mut file) = OpenOptions::new().append(true).open(path) {
+            let _ = file.write_all(&output);
+        }
+    } else {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(&output).and_then(|_| stdout.flush());
     }
-    println!("{MARKER}{output}{MARKER}");
 }
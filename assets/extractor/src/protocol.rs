@@ -0,0 +1,50 @@
+//! Wire protocol between the extractor and `envvars`. This file is shared: it's
+//! included into `envvars` (decoder) as well.
+//!
+//! Output of the extractor:
+//! ```text
+//! MARKER
+//! VERSION         u32 LE
+//! NONCE_LEN       u32 LE
+//! NONCE           NONCE_LEN bytes
+//! COUNT           u32 LE
+//! COUNT times:
+//!     KEY_LEN     u32 LE
+//!     KEY         KEY_LEN bytes
+//!     VALUE_LEN   u32 LE
+//!     VALUE       VALUE_LEN bytes
+//! CHECKSUM        u64 LE
+//! MARKER
+//! ```
+//! Keys and values are raw bytes on unix and UTF-16 (LE) code units on Windows.
+//! Markers are used to find the output of the extractor among other output of shell.
+//! The nonce is taken from environment variable `NONCE_ENVVAR`: `envvars` generates a
+//! new one for each run and accepts only output with it, so output printed by shell's
+//! configuration files (or by a nested run of the extractor) isn't taken as a result.
+//! The checksum (FNV-1a) covers everything from `VERSION` to the last value and
+//! allows to detect truncated or damaged output.
+//!
+//! Output is appended to the file from environment variable `OUTPUT_ENVVAR` (the file
+//! is created by `envvars` in a private folder), so output of shell's configuration
+//! files never mixes with it. If the variable isn't defined, output is sent to stdout.
+
+/// Version of protocol. Should be increased with any change of the format.
+pub const VERSION: u32 = 2;
+
+/// Marks the beginning and the end of extractor's output
+pub const MARKER: &[u8] = include_bytes!("./keys/marker.txt");
+
+/// Environment variable with the nonce of the current run. It isn't included into
+/// the output.
+pub const NONCE_ENVVAR: &str = "ENVVARS_EXTRACTOR_NONCE";
+
+/// Environment variable with the path of the file for output. It isn't included
+/// into the output.
+pub const OUTPUT_ENVVAR: &str = "ENVVARS_EXTRACTOR_OUTPUT";
+
+/// FNV-1a (64 bits) hash of data
+pub fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -1,8 +1,7 @@
 use super::paths;
 use fs_extra::dir;
-use std::io::{Error, ErrorKind};
+use std::io::Error;
 
 pub fn clear() -> Result<(), Error> {
-    dir::remove(paths::extractor_dest_dir()?)
-        .map_err(|e| Error::new(ErrorKind::Other, format!("{e:?}")))
+    dir::remove(paths::extractor_dest_dir()?).map_err(|e| Error::other(format!("{e:?}")))
 }
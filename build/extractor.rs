@@ -1,23 +1,13 @@
 use super::paths;
 use fs_extra::dir;
-use std::{
-    env,
-    fs::rename,
-    io::{Error, ErrorKind},
-    process::Command,
-    str::from_utf8,
-};
+use std::{env, fs::rename, io::Error, process::Command, str::from_utf8};
 
 pub fn copy_sources() -> Result<(), Error> {
     let src = paths::extractor_src_dir()?;
     let dest = paths::extractor_dest_dir()?;
     let extractor = dest.join("extractor");
-    dir::copy(&src, &dest, &dir::CopyOptions::new().overwrite(true)).map_err(|_| {
-        Error::new(
-            ErrorKind::Other,
-            format!("Fail to copy sources from {src:?} to {dest:?}"),
-        )
-    })?;
+    dir::copy(&src, &dest, &dir::CopyOptions::new().overwrite(true))
+        .map_err(|_| Error::other(format!("Fail to copy sources from {src:?} to {dest:?}")))?;
     rename(
         extractor.join("Cargo.toml.hidden"),
         extractor.join("Cargo.toml"),
@@ -37,14 +27,10 @@ pub fn build() -> Result<(), Error> {
         .current_dir(&dest)
         .output()?;
     if !output.status.success() {
-        Err(Error::new(
-            ErrorKind::Other,
-            format!(
-                "Fail to build: {}",
-                from_utf8(&output.stderr)
-                    .expect("Fail to decode output of \"cargo build\" command")
-            ),
-        ))
+        Err(Error::other(format!(
+            "Fail to build: {}",
+            from_utf8(&output.stderr).expect("Fail to decode output of \"cargo build\" command")
+        )))
     } else {
         println!("Build {dest:?} is done");
         println!("Extractor folder: {}", paths::ls(&dest));
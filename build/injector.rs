@@ -8,6 +8,9 @@ use std::{
 };
 use uuid::Uuid;
 
+/// Prefix of extractor's file name. Should be the same as `assets::PREFIX`
+const PREFIX: &str = "envvars-extractor-";
+
 pub fn inject() -> Result<(), Error> {
     let out_dir = paths::cargo_output_dir()?;
     let dest = out_dir.join("assets.rs");
@@ -23,7 +26,7 @@ pub fn inject() -> Result<(), Error> {
             "
 static BIN: &[u8] = &{buffer:?};
 static CHECKSUM: &str = \"{}\";
-static FILENAME: &str = \"{}\";
+static FILENAME: &str = \"{PREFIX}{}\";
         ",
             checksum(&paths::extractor_executable()?)?,
             Uuid::new_v4(),
@@ -20,7 +20,7 @@ end
 namespace :test do
   desc 'Build'
   task :lib do
-    Shell.sh 'cargo test -- --nocapture'
+    Shell.sh 'cargo test --all-features -- --nocapture'
     Reporter.add(Jobs::Test, Owner::Lib, 'tested', '')
   end
 
@@ -1,5 +1,8 @@
 include!(concat!(env!("OUT_DIR"), "/assets.rs"));
 
+/// Prefix of extractor's file name. Should be the same as in `build/injector.rs`
+pub(crate) const PREFIX: &str = "envvars-extractor-";
+
 pub(crate) fn checksum() -> &'static str {
     CHECKSUM
 }
@@ -0,0 +1,198 @@
+use crate::{
+    extractor, process,
+    report::{self, LoadReport},
+    Envvars, Error, LaunchMode,
+};
+use std::{collections::HashMap, ffi::OsString, io, path::PathBuf, process::Output, time::Instant};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+    task, time,
+};
+
+/// Asynchronous version of `process::spawn`: spawning is repeated if the extractor
+/// is still busy (`ETXTBSY`), without blocking the runtime between attempts.
+async fn spawn(command: &mut Command) -> Result<Child, io::Error> {
+    let mut attempt = 0;
+    loop {
+        match command.spawn() {
+            Err(err)
+                if err.kind() == io::ErrorKind::ExecutableFileBusy
+                    && attempt < process::SPAWN_ATTEMPTS =>
+            {
+                attempt += 1;
+                time::sleep(process::POLL_INTERVAL).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Reads the pipe until it's closed. Data over `max` bytes is discarded.
+async fn read<R: AsyncRead + Unpin>(pipe: Option<R>, buffer: &mut Vec<u8>, max: usize) {
+    let Some(mut pipe) = pipe else {
+        return;
+    };
+    let mut chunk = [0; 8192];
+    let mut truncated = false;
+    loop {
+        match pipe.read(&mut chunk).await {
+            Ok(0) => break,
+            Err(err) if process::is_hangup(&err) => break,
+            Ok(n) => truncated |= !process::append(buffer, &chunk[..n], max),
+            Err(err) => {
+                log::warn!("Fail to read output of process: {err}");
+                break;
+            }
+        }
+    }
+    if truncated {
+        log::warn!("Output of process is truncated to {} bytes", buffer.len());
+    }
+}
+
+/// Waits until the shell exits. On unix the shell isn't reaped, so its group still can
+/// be killed safely (see `process::has_exited`).
+#[cfg(unix)]
+async fn exited(child: &mut Child) -> Result<(), io::Error> {
+    let Some(pid) = child.id() else {
+        return Ok(());
+    };
+    while !process::has_exited(pid)? {
+        time::sleep(process::POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn exited(child: &mut Child) -> Result<(), io::Error> {
+    child.wait().await.map(|_| ())
+}
+
+/// Asynchronous version of `Envvars::load`. The extractor is delivered in a blocking
+/// task; the shell is spawned with `kill_on_drop` and its group is killed by a guard,
+/// so dropping the returned future kills the shell with its background processes.
+/// Like in the blocking version pipes kept open by background processes are abandoned
+/// after the shell has exited.
+pub(crate) async fn get(
+    session: Envvars,
+    shell: Option<PathBuf>,
+    args: Vec<String>,
+    mode: LaunchMode,
+) -> Result<(HashMap<OsString, OsString>, LoadReport), Error> {
+    let options = session.options(session.timeout());
+    let (timeout, max_output) = (options.timeout, options.max_output);
+    let (_executable, invocation) =
+        task::spawn_blocking(move || session.prepare(shell.as_ref(), &args, mode))
+            .await
+            .map_err(|e| Error::other(format!("Fail to deliver extractor: {e}")))??;
+    let command_line = report::command_line(&invocation.command);
+    let mut command = invocation.command;
+    let terminal = process::configure(&mut command, options.terminal).map_err(Error::executing)?;
+    let mut command = Command::from(command);
+    command.kill_on_drop(true);
+    let started = Instant::now();
+    let mut child = spawn(&mut command).await.map_err(Error::executing)?;
+    // Command keeps the slave side of the terminal
+    drop(command);
+    #[cfg(unix)]
+    let group = child.id().map(process::Group);
+    let stdout_pipe: Option<Box<dyn AsyncRead + Unpin + Send>> = match terminal {
+        Some(master) => Some(Box::new(File::from_std(master))),
+        None => child
+            .stdout
+            .take()
+            .map(|pipe| Box::new(pipe) as Box<dyn AsyncRead + Unpin + Send>),
+    };
+    let stderr_pipe = child.stderr.take();
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let status = {
+        let work = async {
+            let reading = async {
+                tokio::join!(
+                    read(stdout_pipe, &mut stdout, max_output),
+                    read(stderr_pipe, &mut stderr, max_output)
+                )
+            };
+            tokio::pin!(reading);
+            let mut done = false;
+            let status = tokio::select! {
+                status = exited(&mut child) => status,
+                _ = &mut reading => {
+                    done = true;
+                    exited(&mut child).await
+                }
+            };
+            if !done && time::timeout(process::GRACE_PERIOD, reading).await.is_err() {
+                log::debug!("Output pipes of process are kept open by background processes");
+            }
+            status
+        };
+        if let Some(timeout) = timeout {
+            time::timeout(timeout, work).await.ok()
+        } else {
+            Some(work.await)
+        }
+    };
+    #[cfg(unix)]
+    drop(group);
+    let Some(status) = status else {
+        if let Err(err) = child.kill().await {
+            log::warn!("Fail to kill process: {err}");
+        }
+        return Err(Error::Timeout {
+            timeout: timeout.unwrap_or_default(),
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+        });
+    };
+    status.map_err(Error::executing)?;
+    let duration = started.elapsed();
+    let output = Output {
+        status: child.wait().await.map_err(Error::executing)?,
+        stdout,
+        stderr,
+    };
+    let envvars = extractor::parse(&output, &invocation.channel, &invocation.nonce)?;
+    let report = LoadReport::new(command_line, invocation.mode, &output, duration, &envvars);
+    Ok((envvars, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{get_context_envvars_async, get_profiles};
+
+    #[test]
+    fn test() {
+        let _shared = crate::SHARED.lock().unwrap_or_else(|e| e.into_inner());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Runtime should be created");
+        runtime.block_on(async {
+            let vars = get_context_envvars_async()
+                .await
+                .expect("Envvars should be extracted");
+            assert!(!vars.is_empty());
+            let session = crate::Envvars::new();
+            session.set_pseudo_terminal(true);
+            let vars = session
+                .context_envvars_async()
+                .await
+                .expect("Envvars should be extracted under terminal");
+            assert!(!vars.is_empty());
+            let mut profiles = get_profiles().expect("Profiles should be detected");
+            for profile in profiles.iter_mut() {
+                if let Err(err) = profile.load_async().await {
+                    println!(
+                        "{}: {:?}; fail to get envvars: {err}",
+                        profile.name, profile.path
+                    );
+                }
+            }
+            assert!(profiles.iter().any(|profile| profile.envvars.is_some()));
+        });
+    }
+}
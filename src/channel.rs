@@ -0,0 +1,65 @@
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+/// Prefix of the name of output files
+pub(crate) const PREFIX: &str = "envvars-output-";
+
+#[cfg(unix)]
+fn create_file(path: &Path) -> Result<(), io::Error> {
+    OpenOptions::new()
+        .mode(0o600)
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn create_file(path: &Path) -> Result<(), io::Error> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map(|_| ())
+}
+
+/// Private file, which the extractor writes its output into. Shell's configuration
+/// files don't know about it, so their output never mixes with the extractor's one.
+/// The file is created in the private folder of the extractor and removed on drop.
+pub(crate) struct Channel {
+    path: PathBuf,
+}
+
+impl Channel {
+    pub(crate) fn create(dir: &Path, nonce: &str) -> Result<Self, io::Error> {
+        let path = dir.join(format!("{PREFIX}{nonce}"));
+        create_file(&path)?;
+        Ok(Channel { path })
+    }
+
+    /// Path of the file, which is passed to the extractor
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads everything written by the extractor
+    pub(crate) fn read(&self) -> Result<Vec<u8>, io::Error> {
+        fs::read(&self.path)
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("Fail to remove {:?}: {err}", self.path),
+        }
+    }
+}
@@ -1,63 +1,232 @@
+#[path = "../assets/extractor/src/protocol.rs"]
+mod protocol;
+
 use crate::Error;
-use std::{collections::HashMap, str::from_utf8};
+use std::{collections::HashMap, ffi::OsString};
 
-const KEY_VALUE_SEP: &str = include_str!("../assets/extractor/src/keys/key_value_sep.txt");
-const PAIR_SEP: &str = include_str!("../assets/extractor/src/keys/pair_sep.txt");
-const MARKER: &str = include_str!("../assets/extractor/src/keys/marker.txt");
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt;
 
-fn decode_value(str: &str) -> Option<String> {
-    if let Ok(bytes) = serde_json::from_str::<Vec<u8>>(str) {
-        if let Ok(value) = from_utf8(&bytes) {
-            return Some(value.to_string());
-        }
+#[cfg(windows)]
+use std::os::windows::ffi::OsStringExt;
+
+pub(crate) use protocol::{MARKER, NONCE_ENVVAR, OUTPUT_ENVVAR};
+
+#[cfg(unix)]
+fn decode_value(bytes: &[u8]) -> Result<OsString, Error> {
+    Ok(OsString::from_vec(bytes.to_vec()))
+}
+
+#[cfg(windows)]
+fn decode_value(bytes: &[u8]) -> Result<OsString, Error> {
+    if bytes.len() % 2 != 0 {
+        return Err(Error::invalid_output(
+            "Invalid length of UTF-16 value in extractor output",
+        ));
     }
-    None
+    let wide = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect::<Vec<u16>>();
+    Ok(OsString::from_wide(&wide))
+}
+
+/// Reader of extractor's output
+struct Reader<'a> {
+    data: &'a [u8],
 }
 
-fn decode_pair(pair: &str) -> Option<(String, String)> {
-    let pairs: Vec<&str> = pair.split(KEY_VALUE_SEP).collect();
-    if pairs.len() != 2 {
-        return None;
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            return Err(Error::invalid_output("Extractor output is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
-    let key = decode_value(pairs[0]);
-    let value = decode_value(pairs[1]);
-    if let (Some(key), Some(value)) = (key, value) {
-        Some((key, value))
-    } else {
-        None
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        let bytes = self.take(8)?;
+        let mut buffer = [0u8; 8];
+        buffer.copy_from_slice(bytes);
+        Ok(u64::from_le_bytes(buffer))
     }
+
+    fn value(&mut self) -> Result<OsString, Error> {
+        let len = self.u32()? as usize;
+        decode_value(self.take(len)?)
+    }
+}
+
+/// Positions right after each marker in the output
+fn frames(stdout: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    stdout
+        .windows(MARKER.len())
+        .enumerate()
+        .filter(|(_, window)| *window == MARKER)
+        .map(|(position, _)| position + MARKER.len())
 }
 
-pub(crate) fn decode(stdout: &str) -> Result<HashMap<String, String>, Error> {
-    let mut map: HashMap<String, String> = HashMap::new();
-    let splitted: Vec<&str> = stdout.split(MARKER).collect();
-    if splitted.len() != 3 {
-        return Err(Error::NoExtractorOutput);
+/// Decodes the payload of extractor's output, which starts right after the marker
+/// and the version of protocol.
+fn decode_payload(
+    reader: &mut Reader<'_>,
+    payload: &[u8],
+) -> Result<HashMap<OsString, OsString>, Error> {
+    let count = reader.u32()? as usize;
+    let mut map: HashMap<OsString, OsString> = HashMap::new();
+    for _ in 0..count {
+        let key = reader.value()?;
+        let value = reader.value()?;
+        map.insert(key, value);
+    }
+    let payload = &payload[..payload.len() - reader.data.len()];
+    if reader.u64()? != protocol::checksum(payload) {
+        return Err(Error::invalid_output(
+            "Checksum of extractor output is invalid",
+        ));
+    }
+    if reader.take(MARKER.len())? != MARKER {
+        return Err(Error::invalid_output(
+            "End marker of extractor output isn't found",
+        ));
     }
-    let pairs: Vec<&str> = splitted
-        .get(1)
-        .ok_or(Error::NoExtractorOutput)?
-        .split(PAIR_SEP)
-        .collect();
-    pairs.iter().for_each(|pair| {
-        if let Some((key, value)) = decode_pair(pair) {
-            map.insert(key, value);
-        }
-    });
     Ok(map)
 }
 
+/// Decodes output of the extractor. Only output with the given nonce is accepted; any
+/// other output of shell (including fake or nested outputs of the extractor) is
+/// ignored. An extractor with another version of protocol is rejected with
+/// `Error::UnsupportedProtocol`.
+pub(crate) fn decode(stdout: &[u8], nonce: &str) -> Result<HashMap<OsString, OsString>, Error> {
+    // End markers are found as well, so unknown data after a marker means another
+    // version of protocol only if there are no outputs of the current version
+    let mut unsupported = None;
+    let mut supported = false;
+    for position in frames(stdout) {
+        let payload = &stdout[position..];
+        let mut reader = Reader { data: payload };
+        let Ok(version) = reader.u32() else {
+            continue;
+        };
+        if version != protocol::VERSION {
+            unsupported.get_or_insert(version);
+            continue;
+        }
+        supported = true;
+        if !reader.value().is_ok_and(|value| value == nonce) {
+            continue;
+        }
+        return decode_payload(&mut reader, payload);
+    }
+    Err(match unsupported {
+        Some(found) if !supported => Error::UnsupportedProtocol {
+            found,
+            expected: protocol::VERSION,
+        },
+        _ => Error::NoExtractorOutput,
+    })
+}
+
+/// Generates a nonce for a new run of the extractor
+pub(crate) fn nonce() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Converts environment variables into `String` view. Entries with a key or a value,
+/// which isn't valid Unicode, are skipped (see `non_unicode`).
+pub(crate) fn to_unicode(map: HashMap<OsString, OsString>) -> HashMap<String, String> {
+    map.into_iter()
+        .filter_map(
+            |(key, value)| match (key.into_string(), value.into_string()) {
+                (Ok(key), Ok(value)) => Some((key, value)),
+                (key, _) => {
+                    log::debug!(
+                        "Variable {:?} isn't valid Unicode and is skipped",
+                        key.unwrap_or_else(|key| key.to_string_lossy().to_string())
+                    );
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// Returns keys of environment variables, which are missing in `String` view because
+/// the key or the value isn't valid Unicode.
+///
+/// # Examples
+///
+/// ```
+/// use envvars::{get_context_envvars_os, non_unicode};
+///
+/// let vars = get_context_envvars_os().unwrap();
+///
+/// for key in non_unicode(&vars) {
+///     eprintln!("{key:?} isn't valid Unicode");
+/// }
+/// ```
+pub fn non_unicode(map: &HashMap<OsString, OsString>) -> Vec<&OsString> {
+    map.iter()
+        .filter(|(key, value)| key.to_str().is_none() || value.to_str().is_none())
+        .map(|(key, _)| key)
+        .collect()
+}
+
+#[cfg(test)]
+fn encode(version: u32, nonce: &str, pairs: &[(&[u8], &[u8])]) -> Vec<u8> {
+    #[cfg(unix)]
+    fn put(output: &mut Vec<u8>, value: &[u8]) {
+        output.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        output.extend_from_slice(value);
+    }
+    #[cfg(windows)]
+    fn put(output: &mut Vec<u8>, value: &[u8]) {
+        output.extend_from_slice(&(value.len() as u32 * 2).to_le_bytes());
+        value
+            .iter()
+            .for_each(|byte| output.extend_from_slice(&(*byte as u16).to_le_bytes()));
+    }
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&version.to_le_bytes());
+    put(&mut payload, nonce.as_bytes());
+    payload.extend_from_slice(&(pairs.len() as u32).to_le_bytes());
+    for (key, value) in pairs {
+        put(&mut payload, key);
+        put(&mut payload, value);
+    }
+    let mut output = b"Greetings from .bashrc\n".to_vec();
+    output.extend_from_slice(MARKER);
+    output.extend_from_slice(&payload);
+    output.extend_from_slice(&protocol::checksum(&payload).to_le_bytes());
+    output.extend_from_slice(MARKER);
+    output.extend_from_slice(b"\nBye\n");
+    output
+}
+
 #[test]
 fn test() {
+    let nonce = nonce();
     let pair_01 = ("key_01", "value_01");
     let pair_02 = ("key_02", "value_02");
-    let map = decode(&format!(
-        "{MARKER}{:?}{KEY_VALUE_SEP}{:?}{PAIR_SEP}{:?}{KEY_VALUE_SEP}{:?}{PAIR_SEP}{MARKER}",
-        pair_01.0.as_bytes(),
-        pair_01.1.as_bytes(),
-        pair_02.0.as_bytes(),
-        pair_02.1.as_bytes(),
-    ))
+    let map = decode(
+        &encode(
+            protocol::VERSION,
+            &nonce,
+            &[
+                (pair_01.0.as_bytes(), pair_01.1.as_bytes()),
+                (pair_02.0.as_bytes(), pair_02.1.as_bytes()),
+            ],
+        ),
+        &nonce,
+    )
+    .map(to_unicode)
     .expect("stdout should be decoded");
     assert_eq!(map.len(), 2);
     assert_eq!(
@@ -68,4 +237,52 @@ fn test() {
         map.get(pair_02.0).expect("Should have defined key"),
         pair_02.1
     );
+    // Unknown version of protocol
+    assert!(matches!(
+        decode(&encode(protocol::VERSION + 1, &nonce, &[]), &nonce),
+        Err(Error::UnsupportedProtocol { .. })
+    ));
+    // Truncated output
+    let output = encode(protocol::VERSION, &nonce, &[(b"key", b"value")]);
+    assert!(decode(&output[..output.len() - MARKER.len() - 8], &nonce).is_err());
+    // Damaged output
+    let mut damaged = output.clone();
+    let position = damaged.len() - MARKER.len() - 12;
+    damaged[position] ^= 0xff;
+    assert!(decode(&damaged, &nonce).is_err());
+    assert!(matches!(
+        decode(b"no output", &nonce),
+        Err(Error::NoExtractorOutput)
+    ));
+    // Output with another nonce (printed by rc file) is ignored
+    let mut spoofed = encode(protocol::VERSION, "fake", &[(b"key", b"fake")]);
+    spoofed.extend_from_slice(&output);
+    let map = decode(&spoofed, &nonce)
+        .map(to_unicode)
+        .expect("stdout should be decoded");
+    assert_eq!(map.get("key").map(|v| v.as_str()), Some("value"));
+    assert!(matches!(
+        decode(&encode(protocol::VERSION, "fake", &[]), &nonce),
+        Err(Error::NoExtractorOutput)
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn non_unicode_value() {
+    let nonce = nonce();
+    let map = decode(
+        &encode(
+            protocol::VERSION,
+            &nonce,
+            &[(b"LATIN", b"caf\xe9"), (b"ASCII", b"cafe")],
+        ),
+        &nonce,
+    )
+    .expect("stdout should be decoded");
+    assert_eq!(map.len(), 2);
+    assert_eq!(non_unicode(&map), vec![&OsString::from("LATIN")]);
+    let unicode = to_unicode(map);
+    assert_eq!(unicode.len(), 1);
+    assert_eq!(unicode.get("ASCII").map(|v| v.as_str()), Some("cafe"));
 }
@@ -1,47 +1,211 @@
-use std::{io, path::PathBuf};
+use std::{io, path::PathBuf, time::Duration};
 
-/// Definition of shell profile
-#[derive(thiserror::Error, Debug)]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+fn list(locations: &[(PathBuf, String)]) -> String {
+    locations
+        .iter()
+        .map(|(path, reason)| format!("{path:?}: {reason}"))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Serializes IO error as its kind and message
+fn serialize_io<S: Serializer>(err: &io::Error, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut state = serializer.serialize_struct("IoError", 2)?;
+    state.serialize_field("kind", &format!("{:?}", err.kind()))?;
+    state.serialize_field("message", &err.to_string())?;
+    state.end()
+}
+
+/// Errors of `envvars`. Each variant has a stable machine-readable code (see
+/// `Error::code`). Errors can be serialized (for example to send them into another
+/// process); the code is serialized as field `code`.
+#[derive(thiserror::Error, Debug, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum Error {
     /// Output of extractor isn't found
     #[error("No extractor output")]
     NoExtractorOutput,
-    /// PoisonError error
-    #[error("PoisonError: {0:?}")]
-    PoisonError(String),
+    /// Output of extractor is found, but it's truncated or damaged
+    #[error("Invalid extractor output: {reason}")]
+    InvalidOutput { reason: String },
+    /// Extractor has sent output with unknown version of protocol (for example an
+    /// extractor of another version of `envvars` is used)
+    #[error("Unsupported version of extractor protocol: {found} (expected {expected})")]
+    UnsupportedProtocol { found: u32, expected: u32 },
     /// IO related error
-    #[error("IO Error: {0:?}")]
-    Io(io::Error),
-    /// Happens if stdout has content, which isn't possible to parse as json
-    /// string.
-    #[error("Parsing error: {0:?}")]
-    Parsing(String, Option<i32>, String, String),
+    #[error("IO error")]
+    Io {
+        #[source]
+        #[serde(serialize_with = "serialize_io")]
+        source: io::Error,
+    },
+    /// Happens if the extractor's output cannot be parsed. Includes the reason, exit
+    /// code, stdout and stderr of shell.
+    #[error("Parsing error")]
+    Parsing {
+        #[source]
+        source: Box<Error>,
+        exit_code: Option<i32>,
+        stdout: String,
+        stderr: String,
+    },
     /// Any error during attempt to execute extractor as target shell command
-    #[error("Fail to execute extractor: {0:?}")]
-    Executing(io::Error),
-    /// Happens if by some reasons isn't possible to create extractor in system
-    /// temporary folder
-    #[error("Fail to create extractor: {0:?}")]
-    Create(io::Error),
-    /// Will be dropped if attempt to decode stdout or stderr of shell child process
-    /// is failed
-    #[error("Fail to decode stdout/stderr: {0:?}")]
-    Decoding(std::str::Utf8Error),
+    #[error("Fail to execute extractor")]
+    Executing {
+        #[source]
+        #[serde(serialize_with = "serialize_io")]
+        source: io::Error,
+    },
+    /// Happens if by some reasons isn't possible to create extractor (or its files)
+    #[error("Fail to create extractor")]
+    Create {
+        #[source]
+        #[serde(serialize_with = "serialize_io")]
+        source: io::Error,
+    },
+    /// None of the possible folders can be used for the extractor (for example, all
+    /// of them are mounted with `noexec`). Includes each tried folder with the reason,
+    /// why it cannot be used.
+    #[error("Fail to find a folder to execute extractor from; tried: {}", list(.tried))]
+    NoLocation { tried: Vec<(PathBuf, String)> },
+    /// The extractor or its folder could be changed by another user: it belongs to
+    /// another user, it's writable by group or others or it isn't a regular file. In
+    /// this case the extractor isn't used.
+    #[error("Extractor location {path:?} is insecure: {reason}")]
+    Insecure { path: PathBuf, reason: String },
     /// Shell executable file doesn't exist
-    #[error("Shell executor isn't found: {0:?}")]
-    NotFound(PathBuf),
+    #[error("Shell executor isn't found: {path:?}")]
+    NotFound { path: PathBuf },
+    /// Shell didn't finish in time and has been killed. Includes stdout and stderr
+    /// captured before the shell was killed.
+    #[error("Timeout ({timeout:?}) of extracting is reached")]
+    Timeout {
+        timeout: Duration,
+        stdout: String,
+        stderr: String,
+    },
     /// Target platform isn't supported
     #[error("Platform isn't supported")]
     NotSupportedPlatform,
-    /// Happends on errors related converting paths to strings
-    #[error("Infallible: {0}")]
-    Infallible(std::convert::Infallible),
     /// Some environment variables are needed to detect specific paths on windows,
-    /// like system path, path to program files etc. This error happens if neened
+    /// like system path, path to program files etc. This error happens if needed
     /// variables aren't found
-    #[error("Fail to find envvar: {0}")]
-    NotFoundEnvVar(String),
+    #[error("Fail to find envvar: {name}")]
+    NotFoundEnvVar { name: String },
     /// Any other errors
-    #[error("Other: {0}")]
-    Other(String),
+    #[error("Other: {message}")]
+    Other { message: String },
+}
+
+impl Error {
+    /// Returns stable machine-readable code of error. Codes aren't changed between
+    /// versions, so they can be used for localized messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NoExtractorOutput => "no_extractor_output",
+            Error::InvalidOutput { .. } => "invalid_output",
+            Error::UnsupportedProtocol { .. } => "unsupported_protocol",
+            Error::Io { .. } => "io",
+            Error::Parsing { .. } => "parsing",
+            Error::Executing { .. } => "executing",
+            Error::Create { .. } => "create",
+            Error::NoLocation { .. } => "no_location",
+            Error::Insecure { .. } => "insecure",
+            Error::NotFound { .. } => "not_found",
+            Error::Timeout { .. } => "timeout",
+            Error::NotSupportedPlatform => "not_supported_platform",
+            Error::NotFoundEnvVar { .. } => "not_found_env_var",
+            Error::Other { .. } => "other",
+        }
+    }
+
+    /// Returns true if the error could be temporary and it makes sense to retry the
+    /// operation (for example the shell didn't finish in time or the extractor was
+    /// busy).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout { .. }
+            | Error::Create { .. }
+            | Error::Executing { .. }
+            | Error::InvalidOutput { .. } => true,
+            Error::Parsing { source, .. } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn io(source: io::Error) -> Self {
+        Error::Io { source }
+    }
+
+    pub(crate) fn create(source: io::Error) -> Self {
+        Error::Create { source }
+    }
+
+    pub(crate) fn executing(source: io::Error) -> Self {
+        Error::Executing { source }
+    }
+
+    pub(crate) fn invalid_output(reason: &str) -> Self {
+        Error::InvalidOutput {
+            reason: reason.to_owned(),
+        }
+    }
+
+    pub(crate) fn other<S: Into<String>>(message: S) -> Self {
+        Error::Other {
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test() {
+        let err = Error::Parsing {
+            source: Box::new(Error::create(io::Error::other("busy"))),
+            exit_code: Some(1),
+            stdout: String::new(),
+            stderr: String::from("warning"),
+        };
+        assert_eq!(err.code(), "parsing");
+        assert!(err.is_transient());
+        let source = err.source().expect("Source should be defined");
+        assert_eq!(source.to_string(), "Fail to create extractor");
+        assert_eq!(
+            source.source().map(|err| err.to_string()),
+            Some(String::from("busy"))
+        );
+        assert!(!Error::NotSupportedPlatform.is_transient());
+    }
+
+    #[test]
+    fn serialize() {
+        let errors = [
+            Error::NoExtractorOutput,
+            Error::io(io::Error::other("io")),
+            Error::Timeout {
+                timeout: Duration::from_secs(1),
+                stdout: String::from("out"),
+                stderr: String::from("err"),
+            },
+            Error::UnsupportedProtocol {
+                found: 1,
+                expected: 2,
+            },
+            Error::NotFoundEnvVar {
+                name: String::from("WINDIR"),
+            },
+        ];
+        for err in errors {
+            let value = serde_json::to_value(&err).expect("Error should be serialized");
+            assert_eq!(value["code"], err.code());
+        }
+    }
 }
@@ -1,173 +1,737 @@
-use crate::{assets, checksum::checksum, decoder, Error};
+use crate::{
+    assets,
+    channel::{self, Channel},
+    checksum::checksum,
+    decoder, location, lock, process,
+    report::{self, LoadReport},
+    session, Error, LaunchMode,
+};
 use std::{
     collections::HashMap,
-    env::temp_dir,
-    fs::{remove_file, File, OpenOptions},
+    ffi::OsString,
+    fs::{remove_file, rename, File, OpenOptions},
     io,
     io::Write,
     path::{Path, PathBuf},
     process::{Command, Output},
-    str::from_utf8,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 #[cfg(not(windows))]
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
 
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
+#[cfg(target_os = "linux")]
+use crate::memfd::Memfd;
+
+/// Max time to wait for the extractor during checking of location
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[cfg(windows)]
-fn get_extractor_path() -> PathBuf {
-    temp_dir().join(Path::new(&format!("{}.exe", assets::filename())))
+fn get_extractor_filename() -> String {
+    format!("{}.exe", assets::filename())
 }
 
 #[cfg(not(windows))]
-fn get_extractor_path() -> PathBuf {
-    temp_dir().join(Path::new(assets::filename()))
+fn get_extractor_filename() -> String {
+    assets::filename().to_owned()
+}
+
+#[cfg(not(windows))]
+fn create_file(location: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .mode(0o700)
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(location)
+}
+
+#[cfg(windows)]
+fn create_file(location: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(location)
+}
+
+/// Writes the extractor into a temporary file and moves it into the location. Because
+/// of atomic renaming nobody sees a partially written extractor; processes, which are
+/// running the previous file, aren't affected.
+fn write(location: &Path) -> Result<(), io::Error> {
+    let mut tmp = OsString::from(location.as_os_str());
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    if tmp.exists() {
+        remove_file(&tmp)?;
+    }
+    let result = create_file(&tmp)
+        .and_then(|mut file| {
+            file.write_all(assets::bin())?;
+            file.sync_all()
+        })
+        .and_then(|_| rename(&tmp, location));
+    if result.is_err() && tmp.exists() {
+        if let Err(err) = remove_file(&tmp) {
+            log::warn!("Fail to remove {tmp:?}: {err}");
+        }
+    }
+    result
+}
+
+/// Way of executing the extractor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecutionMode {
+    /// The extractor is written into a file on the disk and executed from there
+    #[default]
+    File,
+    /// Linux only. The extractor is loaded into an anonymous sealed in-memory file
+    /// (`memfd_create`) and executed through `/proc/self/fd/N`. The extractor isn't
+    /// written on the disk (only its short-living output file is) and exactly the
+    /// embedded binary is executed. On other platforms (or if the in-memory file cannot
+    /// be executed) `File` mode is used.
+    Memory,
+}
+
+/// Identity of the extractor's file. If the fingerprint isn't changed since the last
+/// verification, the file isn't hashed again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    #[cfg(not(windows))]
+    dev: u64,
+    #[cfg(not(windows))]
+    ino: u64,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Fingerprint {
+    fn of(location: &Path) -> Result<Self, io::Error> {
+        let metadata = location.metadata()?;
+        Ok(Fingerprint {
+            #[cfg(not(windows))]
+            dev: metadata.dev(),
+            #[cfg(not(windows))]
+            ino: metadata.ino(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+/// Prepared extractor, which is ready to be executed. While it's alive, the file of
+/// the extractor is locked as used and isn't removed by other processes.
+#[derive(Clone)]
+pub(crate) struct Executable {
+    path: PathBuf,
+    /// Private folder for output of the extractor
+    dir: PathBuf,
+    /// Shared lock of the extractor's file
+    _usage: Option<Arc<File>>,
+    /// In-memory file, which should be inherited by the spawned process
+    #[cfg(target_os = "linux")]
+    memfd: Option<Memfd>,
+}
+
+impl Executable {
+    fn file(path: PathBuf, usage: File) -> Self {
+        Executable {
+            dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            path,
+            _usage: Some(Arc::new(usage)),
+            #[cfg(target_os = "linux")]
+            memfd: None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn memory(memfd: &Memfd, dir: &Path) -> Self {
+        Executable {
+            path: memfd.path(),
+            dir: dir.to_path_buf(),
+            _usage: None,
+            memfd: Some(memfd.clone()),
+        }
+    }
+}
+
+/// Makes sure the extractor can be executed, running it directly
+fn check(executable: &Executable) -> Result<(), Error> {
+    let output = process::run(
+        build(executable, None, &[]),
+        &process::Options {
+            timeout: Some(CHECK_TIMEOUT),
+            ..Default::default()
+        },
+    )?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "extractor exited with status {}",
+            output.status
+        )))
+    }
 }
 
 pub struct Extractor {
-    location: PathBuf,
+    /// Way of executing the extractor
+    mode: ExecutionMode,
+    /// In-memory file with the extractor (`ExecutionMode::Memory`) and the private
+    /// folder for its output
+    #[cfg(target_os = "linux")]
+    memfd: Option<(Memfd, PathBuf)>,
+    /// Folder defined by user. `None` - folder will be detected
+    dir: Option<PathBuf>,
+    /// Location of extractor. `None` until the first delivery
+    location: Option<PathBuf>,
+    /// Fingerprint of the last verified extractor's file
+    verified: Option<Fingerprint>,
+    /// true - the checksum is checked before each using, even if the file isn't changed
+    strict: bool,
     /// Field is used only for testing to confirm status of hash checking
     pub(crate) invalid_hash: bool,
+    /// Field is used only for testing to count hash checking
+    pub(crate) hashed: usize,
 }
 
 impl Extractor {
     pub fn new() -> Self {
         Extractor {
-            location: get_extractor_path(),
+            mode: ExecutionMode::File,
+            #[cfg(target_os = "linux")]
+            memfd: None,
+            dir: None,
+            location: None,
+            verified: None,
+            strict: false,
             invalid_hash: false,
+            hashed: 0,
         }
     }
 
-    #[cfg(not(windows))]
-    fn create_file(&self) -> Result<File, io::Error> {
-        OpenOptions::new()
-            .mode(0o777)
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.location)
-    }
-    #[cfg(windows)]
-    fn create_file(&self) -> Result<File, io::Error> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&self.location)
-    }
-
-    fn delivery(&mut self) -> Result<(), io::Error> {
-        if self.location.exists() {
-            log::info!(
-                "Extractor {:?} already exists. Checking checksum.",
-                self.location
-            );
-            if !match checksum(&self.location) {
-                Ok(checksum) => checksum == assets::checksum(),
+    /// Sets folder for the extractor. Location will be detected again with the next
+    /// delivery.
+    pub(crate) fn set_dir(&mut self, dir: Option<PathBuf>) {
+        self.dir = dir;
+        self.location = None;
+        self.verified = None;
+        #[cfg(target_os = "linux")]
+        {
+            self.memfd = None;
+        }
+    }
+
+    /// Sets strict verification: the checksum is checked before each using
+    pub(crate) fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Checks the checksum of the extractor. The result is remembered by fingerprint of
+    /// the file, so the file is hashed again only if it's changed (or in strict mode).
+    fn verify(&mut self, location: &Path) -> bool {
+        let fingerprint = match Fingerprint::of(location) {
+            Ok(fingerprint) => fingerprint,
+            Err(err) => {
+                log::warn!("Fail to get metadata of extractor {location:?}: {err}");
+                self.verified = None;
+                return false;
+            }
+        };
+        if !self.strict && self.verified.as_ref() == Some(&fingerprint) {
+            log::debug!("Extractor {location:?} isn't changed since last verification");
+            return true;
+        }
+        log::info!("Extractor {location:?} already exists. Checking checksum.");
+        self.hashed += 1;
+        let valid = match checksum(&location.to_path_buf()) {
+            Ok(checksum) => checksum == assets::checksum(),
+            Err(err) => {
+                log::warn!("Fail to get checksum of extractor {location:?}: {err}");
+                self.invalid_hash = true;
+                false
+            }
+        };
+        self.verified = if valid { Some(fingerprint) } else { None };
+        valid
+    }
+
+    /// Sets the way of executing the extractor
+    pub(crate) fn set_mode(&mut self, mode: ExecutionMode) {
+        self.mode = mode;
+        #[cfg(target_os = "linux")]
+        if mode != ExecutionMode::Memory {
+            self.memfd = None;
+        }
+    }
+
+    /// Returns in-memory extractor (only Linux in `ExecutionMode::Memory`). `None` means,
+    /// the file based extractor should be used.
+    #[cfg(target_os = "linux")]
+    fn memory(&mut self) -> Option<Executable> {
+        if self.mode != ExecutionMode::Memory {
+            return None;
+        }
+        if self.memfd.is_none() {
+            let memfd = self.output_dir().and_then(|dir| {
+                let memfd = Memfd::new(assets::bin()).map_err(Error::create)?;
+                check(&Executable::memory(&memfd, &dir)).map(|_| (memfd, dir))
+            });
+            match memfd {
+                Ok(memfd) => self.memfd = Some(memfd),
                 Err(err) => {
-                    log::warn!(
-                        "Fail to get checksum of extractor {:?}: {err}",
-                        self.location
-                    );
-                    self.invalid_hash = true;
-                    false
+                    log::warn!("Cannot execute extractor from memory, file is used: {err}");
+                    self.mode = ExecutionMode::File;
+                    return None;
                 }
-            } {
-                remove_file(&self.location)?;
-            } else {
-                return Ok(());
             }
         }
-        let mut file = self.create_file()?;
-        file.write_all(assets::bin())?;
-        file.flush()?;
-        log::debug!("File is written in: {:?}", self.location);
-        Ok(())
+        self.memfd
+            .as_ref()
+            .map(|(memfd, dir)| Executable::memory(memfd, dir))
     }
 
-    #[cfg(not(windows))]
-    fn output(&self, shell: Option<&PathBuf>, args: &[String]) -> Result<Output, io::Error> {
-        if let Some(shell) = shell {
-            Command::new(shell)
-                .args(args.iter())
-                .arg(&self.location)
-                .output()
-        } else {
-            Command::new(&self.location).output()
-        }
-    }
-
-    #[cfg(windows)]
-    fn output(&self, shell: Option<&PathBuf>, args: &[String]) -> Result<Output, io::Error> {
-        if let Some(shell) = shell {
-            const CREATE_NO_WINDOW: u32 = 0x08000000;
-            Command::new(shell)
-                .args(args.iter())
-                .arg(
-                    &self
-                        .location
-                        .to_string_lossy()
-                        .to_string()
-                        .replace('\\', "\\\\"),
-                )
-                .creation_flags(CREATE_NO_WINDOW)
-                .output()
+    #[cfg(not(target_os = "linux"))]
+    fn memory(&mut self) -> Option<Executable> {
+        None
+    }
+
+    /// Returns a private folder for output of the extractor, which is executed from
+    /// memory: the first candidate folder, which can be secured.
+    #[cfg(target_os = "linux")]
+    fn output_dir(&self) -> Result<PathBuf, Error> {
+        let mut tried: Vec<(PathBuf, String)> = Vec::new();
+        for dir in location::candidates(self.dir.as_ref()) {
+            match location::secure_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(err @ Error::Insecure { .. }) => return Err(err),
+                Err(err) => tried.push((dir, err.to_string())),
+            }
+        }
+        Err(Error::NoLocation { tried })
+    }
+
+    /// Delivers the extractor into the location. Deployment is guarded by a lock
+    /// (between threads and processes), so nobody sees a partially written extractor.
+    fn delivery(&mut self, location: &Path) -> Result<Executable, Error> {
+        if let Some(dir) = location.parent() {
+            location::secure_dir(dir)?;
+        }
+        let _deployment = lock::Deployment::acquire(location).map_err(Error::create)?;
+        if location.exists() {
+            location::secure_file(location)?;
+            if self.verify(location) {
+                return Ok(Executable::file(
+                    location.to_path_buf(),
+                    lock::usage(location).map_err(Error::create)?,
+                ));
+            }
+        }
+        write(location).map_err(Error::create)?;
+        log::debug!("File is written in: {location:?}");
+        self.verified = Fingerprint::of(location).ok();
+        if let Some(dir) = location.parent() {
+            if let Err(err) = cleanup_stale_in(dir, false) {
+                log::warn!("Fail to remove stale extractors from {dir:?}: {err}");
+            }
+        }
+        Ok(Executable::file(
+            location.to_path_buf(),
+            lock::usage(location).map_err(Error::create)?,
+        ))
+    }
+
+    /// Makes sure the extractor is in place, isn't damaged and cannot be changed by
+    /// other users. Returns prepared extractor. With the first call the location is
+    /// detected: each candidate folder is checked for the possibility to execute files
+    /// from it. Insecure location isn't skipped silently: `Error::Insecure` is returned.
+    pub(crate) fn prepare(&mut self) -> Result<Executable, Error> {
+        if let Some(executable) = self.memory() {
+            return Ok(executable);
+        }
+        if let Some(location) = self.location.clone() {
+            return self.delivery(&location);
+        }
+        let mut tried: Vec<(PathBuf, String)> = Vec::new();
+        for dir in location::candidates(self.dir.as_ref()) {
+            let location = dir.join(get_extractor_filename());
+            match self
+                .delivery(&location)
+                .and_then(|executable| check(&executable).map(|_| executable))
+            {
+                Ok(executable) => {
+                    log::debug!("Location of extractor: {location:?}");
+                    self.location = Some(location);
+                    return Ok(executable);
+                }
+                Err(err @ Error::Insecure { .. }) => return Err(err),
+                Err(err) => {
+                    log::warn!("Cannot use {dir:?} for extractor: {err}");
+                    if let Err(err) = remove(&location) {
+                        log::warn!("Fail to remove {location:?}: {err}");
+                    }
+                    tried.push((dir, err.to_string()));
+                }
+            }
+        }
+        Err(Error::NoLocation { tried })
+    }
+
+    /// Removes the extractor from the disk. If location isn't detected yet, the
+    /// extractor is removed from all possible locations. The extractor, which is used
+    /// at the moment (by this or another process), isn't removed: an error with kind
+    /// `io::ErrorKind::ResourceBusy` is returned.
+    pub(crate) fn cleanup(&self) -> Result<(), io::Error> {
+        let locations = if let Some(location) = self.location.as_ref() {
+            vec![location.clone()]
         } else {
-            Command::new(&self.location).output()
-        }
-    }
-
-    pub fn get(
-        &mut self,
-        shell: Option<&PathBuf>,
-        args: &[String],
-    ) -> Result<HashMap<String, String>, Error> {
-        self.delivery().map_err(Error::Create)?;
-        let output = self.output(shell, args).map_err(Error::Executing)?;
-        let stdout = from_utf8(&output.stdout).map_err(Error::Decoding)?;
-        let stderr = from_utf8(&output.stderr).map_err(Error::Decoding)?;
-        decoder::decode(stdout).map_err(|e| {
-            Error::Parsing(
-                e.to_string(),
-                output.status.code(),
-                stdout.to_owned(),
-                stderr.to_owned(),
-            )
+            location::candidates(self.dir.as_ref())
+                .into_iter()
+                .map(|dir| dir.join(get_extractor_filename()))
+                .collect()
+        };
+        for location in locations.iter().filter(|location| location.exists()) {
+            remove(location)?;
+        }
+        Ok(())
+    }
+
+    /// Removes extractors of other versions from all possible locations. See
+    /// `envvars::cleanup_stale` for details.
+    pub(crate) fn cleanup_stale(&self) -> Result<Vec<PathBuf>, io::Error> {
+        let mut dirs = location::candidates(self.dir.as_ref());
+        if let Some(dir) = self
+            .location
+            .as_ref()
+            .and_then(|location| location.parent())
+        {
+            if !dirs.iter().any(|candidate| candidate == dir) {
+                dirs.push(dir.to_path_buf());
+            }
+        }
+        let mut removed = Vec::new();
+        for dir in dirs {
+            removed.append(&mut cleanup_stale_in(&dir, false)?);
+        }
+        removed.append(&mut cleanup_stale_in(&std::env::temp_dir(), true)?);
+        Ok(removed)
+    }
+
+    /// Used only for testing to get location of extractor
+    #[cfg(test)]
+    pub(crate) fn location(&self) -> Option<PathBuf> {
+        self.location.clone()
+    }
+}
+
+/// Removes the extractor if nobody uses it. The lock of deployment is held during
+/// removing, so nobody can start using the extractor in between.
+fn remove(location: &Path) -> Result<(), io::Error> {
+    if !location.exists() {
+        return Ok(());
+    }
+    let deployment = lock::Deployment::acquire(location)?;
+    if location.exists() {
+        if lock::is_used(location)? {
+            return Err(io::Error::new(
+                io::ErrorKind::ResourceBusy,
+                format!("Extractor {location:?} is used at the moment"),
+            ));
+        }
+        remove_file(location)?;
+    }
+    deployment.remove()
+}
+
+/// Marker, which is embedded into extractors of all versions (and printed by them)
+const LEGACY_MARKER: &[u8] = decoder::MARKER;
+/// Max size of a file, which is considered as a legacy extractor
+const LEGACY_MAX_SIZE: u64 = 16 * 1024 * 1024;
+/// Temporary files of deployment and output files older than this are considered as
+/// abandoned. Extractors of previous versions younger than this are never removed:
+/// a process of a previous version could be between writing and running of the file.
+const ABANDONED_TMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+fn is_uuid(name: &str) -> bool {
+    name.len() == 36
+        && name.chars().enumerate().all(|(n, c)| match n {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
         })
+}
+
+/// Strips extension of executable file (Windows only)
+fn executable_name(name: &str) -> Option<&str> {
+    if cfg!(windows) {
+        name.strip_suffix(".exe")
+    } else {
+        Some(name)
     }
 }
 
+/// Checks whether the file name belongs to an extractor of another version
+fn is_stale(name: &str) -> bool {
+    name != get_extractor_filename()
+        && executable_name(name)
+            .and_then(|name| name.strip_prefix(assets::PREFIX))
+            .is_some_and(is_uuid)
+}
+
+/// Checks whether the file is an extractor of previous versions, which didn't have a
+/// prefix of the name and were written directly into the system temporary folder.
+/// Such file is recognized by its content: each extractor has embedded marker.
+fn is_legacy(path: &Path, name: &str) -> bool {
+    if !executable_name(name).is_some_and(is_uuid) {
+        return false;
+    }
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata)
+            if metadata.file_type().is_file()
+                && metadata.len() <= LEGACY_MAX_SIZE
+                && location::is_owned(&metadata) => {}
+        _ => return false,
+    }
+    std::fs::read(path).is_ok_and(|content| {
+        content
+            .windows(LEGACY_MARKER.len())
+            .any(|window| window == LEGACY_MARKER)
+    })
+}
+
+/// Checks whether the temporary file of deployment or the output file is abandoned (a
+/// process was interrupted during writing of the extractor or during extracting)
+fn is_abandoned(path: &Path, name: &str) -> bool {
+    if name
+        .strip_prefix(channel::PREFIX)
+        .is_some_and(|nonce| nonce.len() == 32 && nonce.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return is_old(path);
+    }
+    let Some(base) = name
+        .strip_suffix(".tmp")
+        .and_then(|name| name.rsplit_once('.'))
+    else {
+        return false;
+    };
+    (base.0 == get_extractor_filename() || is_stale(base.0))
+        && base.1.chars().all(|c| c.is_ascii_digit())
+        && is_old(path)
+}
+
+fn is_old(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > ABANDONED_TMP_AGE)
+}
+
+/// Removes stale extractors (of other versions) from the folder. Extractors, which
+/// are used at the moment, are skipped. With `legacy` also extractors of previous
+/// versions without prefix of the name are removed. Returns paths of removed files.
+///
+/// Extractors of previous versions and abandoned temporary files aren't locked by
+/// their users, so they are removed only if they are older than `ABANDONED_TMP_AGE`
+/// and no process has them opened or running (see `lock::is_open`). If it cannot be
+/// checked (platforms other than Linux), extractors of previous versions are kept and
+/// abandoned files are removed by age only.
+fn cleanup_stale_in(dir: &Path, legacy: bool) -> Result<Vec<PathBuf>, io::Error> {
+    let mut removed = Vec::new();
+    if !dir.is_dir() {
+        return Ok(removed);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let result = if is_stale(name) {
+            remove(&path)
+        } else if legacy && is_legacy(&path, name) {
+            if !is_old(&path) || lock::is_open(&path) != Some(false) {
+                log::debug!("Extractor of previous version could be in use: {path:?}");
+                continue;
+            }
+            remove_file(&path)
+        } else if is_abandoned(&path, name) {
+            if lock::is_open(&path) == Some(true) {
+                continue;
+            }
+            remove_file(&path)
+        } else if let Some(extractor) = name.strip_suffix(".lock").filter(|n| is_stale(n)) {
+            // Lock file of already removed extractor
+            let extractor = dir.join(extractor);
+            if extractor.exists() {
+                continue;
+            }
+            lock::Deployment::acquire(&extractor).and_then(|deployment| {
+                if extractor.exists() {
+                    Ok(())
+                } else {
+                    deployment.remove()
+                }
+            })
+        } else {
+            continue;
+        };
+        match result {
+            Ok(()) => {
+                log::debug!("Stale file is removed: {path:?}");
+                removed.push(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("Fail to remove stale file {path:?}: {err}"),
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(not(windows))]
+fn build(executable: &Executable, shell: Option<&PathBuf>, args: &[String]) -> Command {
+    let mut command = if let Some(shell) = shell {
+        let mut command = Command::new(shell);
+        command.args(args.iter()).arg(&executable.path);
+        command
+    } else {
+        Command::new(&executable.path)
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(memfd) = executable.memfd.as_ref() {
+        memfd.inherit(&mut command);
+    }
+    command
+}
+
+#[cfg(windows)]
+fn build(executable: &Executable, shell: Option<&PathBuf>, args: &[String]) -> Command {
+    let location = &executable.path;
+    if let Some(shell) = shell {
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        let mut command = Command::new(shell);
+        command
+            .args(args.iter())
+            .arg(location.to_string_lossy().to_string().replace('\\', "\\\\"))
+            .creation_flags(CREATE_NO_WINDOW);
+        command
+    } else {
+        Command::new(location)
+    }
+}
+
+/// Prepared run of the extractor: the command, the nonce, which the extractor's
+/// output should have, the channel for the output and the launch mode of the shell
+pub(crate) struct Invocation {
+    pub(crate) command: Command,
+    pub(crate) nonce: String,
+    pub(crate) channel: Channel,
+    pub(crate) mode: LaunchMode,
+}
+
+/// Creates a command to run the extractor (directly or with the shell). Each command
+/// gets its own nonce and its own output file.
+pub(crate) fn command(
+    executable: &Executable,
+    shell: Option<&PathBuf>,
+    args: &[String],
+    mode: LaunchMode,
+) -> Result<Invocation, Error> {
+    let nonce = decoder::nonce();
+    let channel = Channel::create(&executable.dir, &nonce).map_err(Error::create)?;
+    let mut command = build(executable, shell, args);
+    command
+        .env(decoder::NONCE_ENVVAR, &nonce)
+        .env(decoder::OUTPUT_ENVVAR, channel.path());
+    Ok(Invocation {
+        command,
+        nonce,
+        channel,
+        mode,
+    })
+}
+
+/// Runs prepared command of extractor and parses its output. Returns environment
+/// variables and the report of loading. If timeout is defined and the shell doesn't
+/// finish in time, it will be killed and `Error::Timeout` returned. See
+/// `process::Options` for other options.
+pub(crate) fn run(
+    invocation: Invocation,
+    options: &process::Options,
+) -> Result<(HashMap<OsString, OsString>, LoadReport), Error> {
+    let command = report::command_line(&invocation.command);
+    let started = Instant::now();
+    let output = process::run(invocation.command, options)?;
+    let duration = started.elapsed();
+    let envvars = parse(&output, &invocation.channel, &invocation.nonce)?;
+    let report = LoadReport::new(command, invocation.mode, &output, duration, &envvars);
+    Ok((envvars, report))
+}
+
+/// Parses output of the extractor from the channel. Only output with the given nonce
+/// is accepted. Stdout and stderr of the shell are used only for diagnostics.
+pub(crate) fn parse(
+    output: &Output,
+    channel: &Channel,
+    nonce: &str,
+) -> Result<HashMap<OsString, OsString>, Error> {
+    channel
+        .read()
+        .map_err(Error::io)
+        .and_then(|data| decoder::decode(&data, nonce))
+        .map_err(|e| match e {
+            Error::UnsupportedProtocol { .. } => e,
+            e => Error::Parsing {
+                source: Box::new(e),
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            },
+        })
+}
+
 impl Default for Extractor {
     fn default() -> Self {
         Self::new()
     }
 }
 
-/// Removes extractor file from OS temporary folder. `envvars` creates a small
-/// executable file in OS temporary folder. This application drops a list of
-/// available environment variables and does nothing else. As soon as the
-/// extractor has been created, `envvars` uses it. But it still can be safely
-/// removed for cleaning purposes for example before closing of an application.
+/// Removes extractor file from the disk. `envvars` creates a small executable file
+/// in OS temporary folder (or another folder, see `set_extractor_dir`). This
+/// application drops a list of available environment variables and does nothing
+/// else. As soon as the extractor has been created, `envvars` uses it. But it
+/// still can be safely removed for cleaning purposes for example before closing
+/// of an application.
 ///
 /// If `envvars` doesn't detect an extractor, it will be created again.
 ///
-/// Note, `envvars` doesn't remove an extractor application automatically.
+/// Note, `envvars` removes the extractor automatically only if a guard is used (see
+/// `init`).
 pub fn cleanup() -> Result<(), io::Error> {
-    let path = get_extractor_path();
-    if !path.exists() {
-        Ok(())
-    } else {
-        remove_file(&path)
-    }
+    session::default().cleanup()
+}
+
+/// Removes extractors left by other (previous) versions of `envvars`. Each build of
+/// `envvars` has its own extractor named `envvars-extractor-<uuid>`, so after updating
+/// of an application old extractors stay on the disk. This function looks for them in
+/// all possible locations of the extractor (including files of very old versions in
+/// the system temporary folder) and removes them. Extractors, which are used at the
+/// moment by other processes, are skipped. Files of very old versions are removed only
+/// on Linux, if they are older than an hour and no process has them opened or running.
+/// Returns paths of removed files.
+///
+/// Stale extractors are also removed from the folder automatically each time a new
+/// extractor is written into it.
+pub fn cleanup_stale() -> Result<Vec<PathBuf>, io::Error> {
+    session::default().cleanup_stale()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{profiles::get as get_profiles, Profile, EXTRACTOR};
+    use crate::{fixture::TempDir, load_all, profiles::get as get_profiles, Envvars, Profile};
 
     fn extract() -> Result<(), Error> {
         let mut profiles = get_profiles()?;
@@ -197,7 +761,12 @@ mod tests {
         println!("Found shells with failed detection of envvars:");
         println!("{}", "=".repeat(50));
         failed.iter().for_each(|(p, err)| match err {
-            Error::Parsing(_err, code, stdout, stderr) => {
+            Error::Parsing {
+                exit_code: code,
+                stdout,
+                stderr,
+                ..
+            } => {
                 println!("{}: {:?}; code: {code:?}", p.name, p.path,);
                 println!("{}: {:?}; stdout:\n{stdout}", p.name, p.path,);
                 println!("{}: {:?}; stderr:\n{stderr}", p.name, p.path,);
@@ -210,10 +779,13 @@ mod tests {
     }
     #[test]
     fn test() {
+        let _shared = crate::SHARED.lock().unwrap_or_else(|e| e.into_inner());
         // Extracting
         extract().expect("Envvars should be extracted");
         // Remove extractor
-        let extractor_path = get_extractor_path();
+        let extractor_path = session::default()
+            .location()
+            .expect("Extractor should be delivered");
         remove_file(&extractor_path).expect("Extractor should removed");
         // Extracting again
         extract().expect("Envvars should be extracted");
@@ -230,6 +802,293 @@ mod tests {
         extract().expect("Envvars should be extracted");
         // Extractor should detect changes on executable file with invalid hash and rewrite
         // executable file again
-        assert!(!EXTRACTOR.lock().expect("Access to extractor").invalid_hash);
+        assert!(!session::default().invalid_hash());
+    }
+
+    #[test]
+    fn concurrent() {
+        let _shared = crate::SHARED.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sequential = get_profiles().expect("Profiles should be detected");
+        sequential.iter_mut().for_each(|p| {
+            let _ = p.load();
+        });
+        let mut concurrent = get_profiles().expect("Profiles should be detected");
+        let results = load_all(&mut concurrent, 0).expect("Extractor should be delivered");
+        assert_eq!(results.len(), concurrent.len());
+        sequential
+            .iter()
+            .zip(concurrent.iter().zip(results.iter()))
+            .for_each(|(sequential, (concurrent, result))| {
+                assert_eq!(sequential.path, concurrent.path);
+                assert_eq!(result.is_ok(), concurrent.envvars.is_some());
+                assert_eq!(sequential.envvars.is_some(), concurrent.envvars.is_some());
+            });
+    }
+
+    #[test]
+    fn location() {
+        let session = Envvars::new();
+        // File instead of folder cannot be used as location
+        let file = TempDir::new("not_a_folder");
+        File::create(file.path()).expect("File should be created");
+        session.set_extractor_dir(Some(file.path().to_path_buf()));
+        match session.context_envvars() {
+            Err(Error::NoLocation { tried }) => {
+                assert_eq!(tried.len(), 1);
+                assert_eq!(tried[0].0, file.path());
+            }
+            Err(err) => panic!("Expected NoLocation error, but got: {err}"),
+            Ok(_) => panic!("Expected NoLocation error, but envvars are extracted"),
+        }
+        // Custom folder
+        let dir = TempDir::new("custom");
+        session.set_extractor_dir(Some(dir.path().to_path_buf()));
+        assert!(!session
+            .context_envvars()
+            .expect("Envvars should be extracted")
+            .is_empty());
+        assert!(session
+            .location()
+            .expect("Extractor should be delivered")
+            .starts_with(dir.path()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn insecure() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+        let dir = TempDir::new("insecure");
+        fs::create_dir_all(dir.path()).expect("Folder should be created");
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777))
+            .expect("Permissions should be set");
+        let session = dir.session();
+        match session.context_envvars() {
+            Err(Error::Insecure { path, .. }) => assert_eq!(path, dir.path()),
+            Err(err) => panic!("Expected Insecure error, but got: {err}"),
+            Ok(_) => panic!("Expected Insecure error, but envvars are extracted"),
+        }
+        // Private folder is used
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700))
+            .expect("Permissions should be set");
+        assert!(session.context_envvars().is_ok());
+        let location = session.location().expect("Extractor should be delivered");
+        let mode = fs::metadata(&location)
+            .expect("Extractor should exist")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o077, 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn memory() {
+        let session = Envvars::new();
+        session.set_execution_mode(ExecutionMode::Memory);
+        let vars = session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert!(!vars.is_empty());
+        let shell = Profile::new(&PathBuf::from("/bin/sh"), vec!["-c"], None)
+            .expect("Profile should be created");
+        assert!(!session
+            .load(&shell)
+            .expect("Envvars should be extracted")
+            .is_empty());
+        // Nothing is written on the disk
+        assert!(session.location().is_none());
+    }
+
+    #[test]
+    fn deployment() {
+        let dir = TempDir::new("deployment");
+        // Many sessions (like many processes) are delivering the extractor at once
+        let results = std::thread::scope(|scope| {
+            (0..8)
+                .map(|_| scope.spawn(|| dir.session().context_envvars()))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().expect("Thread should be joined"))
+                .collect::<Vec<_>>()
+        });
+        assert!(results.iter().all(|result| result.is_ok()));
+        // Used extractor isn't removed
+        let session = dir.session();
+        let executable = session.executable().expect("Extractor should be delivered");
+        match session.cleanup() {
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::ResourceBusy),
+            Ok(()) => panic!("Used extractor should not be removed"),
+        }
+        drop(executable);
+        session.cleanup().expect("Extractor should be removed");
+        assert_eq!(
+            std::fs::read_dir(dir.path())
+                .expect("Folder should be read")
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn verification() {
+        let dir = TempDir::new("verification");
+        let session = dir.session();
+        // Just written extractor isn't hashed
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert_eq!(session.hashed(), 0);
+        // Strict mode
+        session.set_strict_verification(true);
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert_eq!(session.hashed(), 1);
+        session.set_strict_verification(false);
+        // Changed extractor is hashed again
+        let location = session.location().expect("Extractor should be delivered");
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&location)
+            .expect("Extractor file should be opened");
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .expect("Modification time should be set");
+        drop(file);
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert_eq!(session.hashed(), 2);
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert_eq!(session.hashed(), 2);
+    }
+
+    #[test]
+    fn stale() {
+        let dir = TempDir::new("stale");
+        let stale = |uuid: &str| {
+            let name = format!("{}{uuid}", assets::PREFIX);
+            dir.join(if cfg!(windows) {
+                format!("{name}.exe")
+            } else {
+                name
+            })
+        };
+        let session = dir.session();
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        let current = session.location().expect("Extractor should be delivered");
+        let unused = stale("00000000-0000-0000-0000-000000000000");
+        let used = stale("00000000-0000-0000-0000-000000000001");
+        let unrelated = dir.join("unrelated");
+        for path in [&unused, &used, &unrelated] {
+            std::fs::write(path, b"stale").expect("File should be written");
+        }
+        std::fs::write(lock::path(&unused), b"").expect("Lock file should be written");
+        let usage = lock::usage(&used).expect("Stale extractor should be locked");
+        let removed = session
+            .cleanup_stale()
+            .expect("Stale extractors should be removed");
+        assert!(removed.contains(&unused));
+        assert!(!unused.exists());
+        assert!(!lock::path(&unused).exists());
+        // Used, current and unrelated files are kept
+        assert!(used.exists());
+        assert!(current.exists());
+        assert!(unrelated.exists());
+        drop(usage);
+        // Stale extractors are removed on deployment of a new extractor
+        session.cleanup().expect("Extractor should be removed");
+        session
+            .context_envvars()
+            .expect("Envvars should be extracted");
+        assert!(!used.exists());
+        assert!(current.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn legacy() {
+        let dir = TempDir::new("legacy");
+        std::fs::create_dir_all(dir.path()).expect("Folder should be created");
+        let legacy = dir.join("00000000-0000-0000-0000-000000000002");
+        let mut content = b"legacy".to_vec();
+        content.extend_from_slice(LEGACY_MARKER);
+        std::fs::write(&legacy, &content).expect("File should be written");
+        // Just written file could be executed soon
+        cleanup_stale_in(dir.path(), true).expect("Folder should be checked");
+        assert!(legacy.exists());
+        let file = File::options()
+            .write(true)
+            .open(&legacy)
+            .expect("File should be opened");
+        file.set_modified(SystemTime::now() - ABANDONED_TMP_AGE * 2)
+            .expect("Time should be set");
+        // Opened file is used
+        cleanup_stale_in(dir.path(), true).expect("Folder should be checked");
+        assert!(legacy.exists());
+        drop(file);
+        let removed = cleanup_stale_in(dir.path(), true).expect("Folder should be checked");
+        assert_eq!(removed, vec![legacy]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let dir = TempDir::new("non_unicode");
+        let session = dir.session();
+        let key = OsStr::from_bytes(b"ENVVARS_LATIN_1");
+        let value = OsStr::from_bytes(b"/home/caf\xe9");
+        let shell = PathBuf::from("/bin/sh");
+        for shell in [None, Some(&shell)] {
+            let (executable, mut invocation) = session
+                .prepare(shell, &[String::from("-c")], LaunchMode::Plain)
+                .expect("Extractor should be delivered");
+            invocation.command.env(key, value);
+            let (envvars, _) =
+                run(invocation, &process::Options::default()).expect("Envvars should be extracted");
+            drop(executable);
+            assert_eq!(envvars.get(key).map(|v| v.as_os_str()), Some(value));
+            assert_eq!(crate::non_unicode(&envvars), vec![key]);
+            assert!(!decoder::to_unicode(envvars).is_empty());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spoofing() {
+        let dir = TempDir::new("spoofing");
+        let session = dir.session();
+        // Shell prints a banner with a marker and runs extractor with another nonce
+        // before and after the real one
+        let script = format!(
+            "echo Welcome{1}; SPOOFED=1 {0}=fake \"$0\"; \"$0\"; SPOOFED=1 {0}=fake \"$0\"",
+            decoder::NONCE_ENVVAR,
+            String::from_utf8_lossy(decoder::MARKER),
+        );
+        let (executable, invocation) = session
+            .prepare(
+                Some(&PathBuf::from("/bin/sh")),
+                &[String::from("-c"), script],
+                LaunchMode::Plain,
+            )
+            .expect("Extractor should be delivered");
+        let (envvars, report) =
+            run(invocation, &process::Options::default()).expect("Envvars should be extracted");
+        drop(executable);
+        assert!(!envvars.is_empty());
+        assert!(!envvars.contains_key(&OsString::from("SPOOFED")));
+        assert!(!envvars.contains_key(&OsString::from(decoder::NONCE_ENVVAR)));
+        assert!(!envvars.contains_key(&OsString::from(decoder::OUTPUT_ENVVAR)));
+        // Output of shell is available in report
+        assert!(report.success);
+        assert!(report.stdout.starts_with("Welcome"));
+        assert!(report.command.starts_with("\"/bin/sh\" \"-c\""));
+        assert!(report.dropped.is_empty());
     }
 }
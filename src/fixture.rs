@@ -0,0 +1,59 @@
+use crate::{assets, Envvars};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Temporary folder of a test. Each test uses its own name, so tests don't interfere
+/// with each other. The folder (with everything inside) is removed on drop, so it's
+/// removed even if the test fails.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Returns a guard of the folder with the given name in the system temporary folder.
+    /// Leftovers of previous runs are removed; the folder itself isn't created.
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = TempDir {
+            path: env::temp_dir().join(format!("{}.{name}", assets::filename())),
+        };
+        dir.remove();
+        dir
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns path of the file in the folder
+    pub(crate) fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// Creates a new session, which delivers the extractor into this folder
+    pub(crate) fn session(&self) -> Envvars {
+        let session = Envvars::new();
+        session.set_extractor_dir(Some(self.path.clone()));
+        session
+    }
+
+    fn remove(&self) {
+        let result = if self.path.is_dir() {
+            fs::remove_dir_all(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+        if let Err(err) = result {
+            if err.kind() != io::ErrorKind::NotFound {
+                log::warn!("Fail to remove {:?}: {err}", self.path);
+            }
+        }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
@@ -44,6 +44,64 @@
 //! assert!(vars.contains_key("PATH") || vars.contains_key("Path") || vars.contains_key("path"));
 //! ```
 //!
+//! Using own session with independent settings. Free functions (`get_context_envvars`,
+//! `load_all`, `Profile::load` etc.) are using a default session.
+//!
+//! ```
+//! use std::time::Duration;
+//! use envvars::{get_profiles, Envvars};
+//!
+//! let session = Envvars::new();
+//! session.set_timeout(Some(Duration::from_secs(10)));
+//!
+//! for profile in get_profiles().unwrap() {
+//!     if let Err(err) = session.load(&profile) {
+//!         eprintln!("Cannot load envvars for {}: {err}", profile.name);
+//!     }
+//! }
+//! ```
+//!
+//! ## Features
+//!
+//! - `async` - asynchronous versions of loading functions: `Profile::load_async` and
+//!   `get_context_envvars_async` (based on `tokio`). Dropping of a future kills the
+//!   spawned shell.
+//!
+//! ## Load report
+//!
+//! `Profile::load` (as well as `load_all` and `Envvars::load_profile`) returns
+//! `LoadReport` with details of loading: executed command line, exit status, duration,
+//! everything the shell (its configuration files) printed into `stdout` and `stderr` and
+//! keys of skipped (non-Unicode) variables. The report is serializable and could be used
+//! to diagnose shells, which don't work as expected.
+//!
+//! ## Errors
+//!
+//! Each `Error` has a stable machine-readable code (see `Error::code`) and can be
+//! serialized (based on `serde`) with it, so a frontend could show localized messages
+//! and decide, when to retry (see `Error::is_transient`). The underlying error (if any)
+//! is available through `std::error::Error::source`.
+//!
+//! ## Launch mode
+//!
+//! Which configuration files a shell reads depends on how it's started. `LaunchMode`
+//! (`Login`, `Interactive`, `LoginInteractive` or `Plain`) defines it. Each kind of shell
+//! (`ShellKind`: `bash`, `zsh`, `fish`, `tcsh`, `pwsh`, `nu` etc., detected by the name
+//! of the executable file or the target of symlink) gets its own flags for each mode
+//! (for example `-i -l` for `bash` or `-Login -NoLogo` for `pwsh`). The mode is taken
+//! from `Profile::mode` (`LoginInteractive` for detected profiles, `Plain` for profiles
+//! created with `Profile::new`) or given for a single loading with
+//! `Profile::load_with_mode`, and it's recorded in `LoadReport::mode`. Results don't
+//! depend on whether the application itself is started in a terminal.
+//!
+//! ## Non-Unicode data
+//!
+//! Keys and values of environment variables aren't always valid Unicode (for example
+//! Latin-1 paths on legacy systems). Such variables are skipped in
+//! `HashMap<String, String>`, but they are available as they are in `OsString` based
+//! maps: `Profile::envvars_os`, `get_context_envvars_os()`, `Envvars::load_os`. Skipped
+//! variables are reported by `Profile::non_unicode()` and `non_unicode(&map)`.
+//!
 //! ## Diffrence from `std::env::vars`
 //!
 //! `envvars` actually executes each found `shell` it means: all settings of the target
@@ -55,27 +113,100 @@
 //! ## How it works
 //!
 //! Under the hood, `envvars` takes each shell, and executes it with a command,
-//! which posts a list of environment variables to `stdout`. As soon as executing
-//! is done, `envvars` reads `stdout` and parse environment variables into
+//! which writes a list of environment variables into a private file. As soon as
+//! executing is done, `envvars` reads the file and parse environment variables into
 //! `HashMap<String, String>`.
 //!
+//! The extractor sends environment variables as raw bytes in a compact length-prefixed
+//! format with a version header. An extractor with an unknown version of the protocol
+//! is rejected with `Error::UnsupportedProtocol`. Each run of the extractor gets a random
+//! nonce (environment variable `ENVVARS_EXTRACTOR_NONCE`, which isn't included into
+//! results) and only output with this nonce is accepted, so output of rc files or of
+//! nested runs of the extractor cannot spoof results. A checksum of the output allows
+//! to detect truncated or damaged data.
+//!
+//! The extractor doesn't use `stdout`: the output is written into a private
+//! short-living file (its path is passed with environment variable
+//! `ENVVARS_EXTRACTOR_OUTPUT`), so banners and other messages of shell's configuration
+//! files never mix with the data. `stdout` and `stderr` of the shell are used only for
+//! diagnostics (see `Error::Parsing`); not more than 1 MiB of each is kept
+//! (`Envvars::set_max_output`).
+//!
+//! The shell is started without `stdin` (it cannot wait for user input) and on unix
+//! in its own process group. Configuration files could start background processes
+//! (`ssh-agent`, `gpg-agent`, `tmux` server etc.), which keep output pipes of the shell
+//! open; `envvars` doesn't wait for them: as soon as the shell has exited, the rest of
+//! its output is read during a short grace period and all processes left in its group
+//! are killed.
+//!
+//! By default the shell doesn't have a terminal, so sections of configuration files,
+//! which are guarded by TTY checks, are skipped. `Envvars::set_pseudo_terminal(true)`
+//! (unix only) runs the shell under a new pseudo-terminal, which becomes its
+//! controlling terminal, so the shell sees a real TTY regardless of how the
+//! application was started. Input isn't available in this mode either: reading from
+//! the terminal returns end of file immediately.
+//!
 //! As soon as extracting process could take a sensitive time (~1sec on windows
 //! and ~10ms on Unix-based OS), `envvars` doesn't extract environment variables
 //! during detecting the shell's profiles. That's the developer's decision when it
 //! should be done for the selected or each profile.
 //!
-//! `envvars` creates a small executable application in the system's temporary folder.
-//! This application is used to "drop" list of environment variables into `stdout`
-//! of the parent process and does nothing else. As soon as `envvars` instance is
-//! dropped, the application would be removed from the disk.
+//! `envvars` creates a small executable application (extractor) on the disk. The folder
+//! for the extractor can be defined with `set_extractor_dir` (`Envvars::set_extractor_dir`)
+//! or with environment variable `ENVVARS_EXTRACTOR_DIR`. If it isn't defined, `envvars`
+//! tries `$XDG_RUNTIME_DIR/envvars` (unix), the cache folder of user (for example
+//! `~/.cache/envvars`) and a private folder of user in the system's temporary folder (for
+//! example `/tmp/envvars-1000`); the first folder, which allows executing files (isn't
+//! mounted with `noexec`), is used.
+//! This application is used to "drop" list of environment variables into a private
+//! output file, which is read by the parent process, and does nothing else. The
+//! extractor stays on the disk to be reused; to remove it automatically keep a guard
+//! returned by `init()` (or `Envvars::guard()`): as soon as the last guard is dropped,
+//! the extractor is removed, if it isn't used by another process.
 //!
-//! For security reasons `envvars` checks the checksum of the extractor each time
-//! before using it. If a checksum is invalid (the file was damaged/changed etc),
-//! `envars` will remove a corrupted file and create a new one.
+//! For security reasons the extractor is created with permissions `0o700` in a folder,
+//! which belongs to the current user; before each using `envvars` checks that neither
+//! the extractor nor its folder belong to another user or are writable by group or
+//! others (otherwise `Error::Insecure` is returned). Also `envvars` checks the checksum
+//! of the extractor before using it. The result of verification is remembered by the
+//! identity of the file (device, inode, size and modification time), so the file is
+//! hashed again only if it has been changed; `Envvars::set_strict_verification(true)`
+//! forces checking of the checksum before each using. If a checksum is invalid (the
+//! file was damaged/changed etc), `envars` will remove a corrupted file and create a
+//! new one.
 //!  
+//! On Linux the extractor can be executed without writing it on the disk:
+//! `Envvars::set_execution_mode(ExecutionMode::Memory)` loads the extractor into an
+//! anonymous sealed in-memory file (`memfd_create`), which is executed through
+//! `/proc/self/fd/N`. On other platforms the file based extractor is used.
+//!
+//! Each build of `envvars` has its own extractor (`envvars-extractor-<uuid>`). Extractors
+//! of other versions are removed from the folder as soon as a new extractor is written
+//! into it; `cleanup_stale()` looks for them in all possible locations. Extractors, which
+//! are used by other processes at the moment, are never removed. Extractors of very old
+//! versions (in the system temporary folder without prefix of the name) don't mark
+//! themselves as used, so they are removed only on Linux, if they are older than an
+//! hour and no process has them opened or running.
+//!
 //! ## Unix specific
 //!
-//! `envvars` reads `/etc/shells` and analyze each shell from a list
+//! `envvars` reads `/etc/shells` and analyze each shell from a list. Shells, which aren't
+//! registered there (installed with Homebrew on Linux, Nix, cargo (`~/.cargo/bin/nu`),
+//! conda or just copied into `~/.local/bin`), are looked for in folders of `PATH` and in
+//! well-known installation prefixes. `Profile::source` tells where the shell has been
+//! found. Missing `/etc/shells` isn't an error.
+//!
+//! The default shell of user (from the account database, or `SHELL` if it isn't
+//! available there or it isn't a real shell, like `nologin` of a service account) is
+//! marked with `Profile::is_default` and goes first in the list of profiles; the shell,
+//! which has launched the application (Linux only), is marked with `Profile::is_parent`
+//! and goes next. See `default_shell()` and `parent_shell()`.
+//!
+//! On systems with merged `/usr` the same shell is listed twice (`/bin/bash` and
+//! `/usr/bin/bash`). `Profile::canonical_path` has the path with resolved symlinks;
+//! `get_profiles_with(DetectOptions { deduplicate: true })` groups such profiles (other
+//! paths are available in `Profile::aliases`). Links to multi-call binaries (`sh` and
+//! `ash` of `busybox`) with different names aren't grouped.
 //!
 //! ## Windows specific
 //!
@@ -91,31 +222,57 @@
 //! ## Guaranteed results
 //!
 //! Because `envvars` tries to initialize each shell and "drop" a list of environment
-//! variables to `stdout`, the shell should support the possibility to put a command as
-//! an argument, for example: `/bin/bash -c path_to_command`. Obviously not many, but
-//! still some shells don't support it (like windows command prompt). In this case, you
-//! still can use `get_context_envvars()` to get a list of environment variables without
-//! the shell's context.
+//! variables with the extractor started by the shell, the shell should support the
+//! possibility to put a command as an argument, for example:
+//! `/bin/bash -c path_to_command`. Obviously not many, but still some shells don't
+//! support it (like windows command prompt). In this case, you still can use
+//! `get_context_envvars()` to get a list of environment variables without the shell's
+//! context.
+//!
+//! To find such shells in advance use `Profile::probe()`: it quickly (without login and
+//! configuration files) checks whether the shell can be executed and accepts a command,
+//! and reports its version and kind. The result is cached in `Profile::info`.
 //!
 
 #[macro_use]
 extern crate lazy_static;
-use std::{collections::HashMap, sync::Mutex};
+use std::{collections::HashMap, ffi::OsString, path::PathBuf, time::Duration};
 mod assets;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod channel;
 mod checksum;
 mod decoder;
 mod error;
 mod extractor;
+#[cfg(test)]
+mod fixture;
+mod location;
+mod lock;
+#[cfg(target_os = "linux")]
+mod memfd;
+mod process;
 mod profiles;
+#[cfg(unix)]
+mod pty;
+mod report;
+mod session;
 
+pub use decoder::non_unicode;
 pub use error::Error;
-pub use extractor::cleanup;
-use extractor::Extractor;
-pub use profiles::{get as get_profiles, Profile};
+pub use extractor::{cleanup, cleanup_stale, ExecutionMode};
+pub use profiles::{
+    default_shell, get as get_profiles, get_with as get_profiles_with, load_all, parent_shell,
+    DetectOptions, LaunchMode, Profile, ProfileSource, ShellInfo, ShellKind,
+};
+pub use report::LoadReport;
+pub use session::{Envvars, Guard};
 
+#[cfg(test)]
 lazy_static! {
-    #[doc(hidden)]
-    static ref EXTRACTOR: Mutex<Extractor> = Mutex::new(Extractor::new());
+    /// Used by tests, which are working with the shared extractor, to prevent damaging
+    /// of the extractor by one test while another test uses it.
+    pub(crate) static ref SHARED: std::sync::Mutex<()> = std::sync::Mutex::new(());
 }
 
 /// Extract environment variables without shell context.
@@ -131,8 +288,116 @@ lazy_static! {
 /// assert!(vars.contains_key("PATH") || vars.contains_key("Path") || vars.contains_key("path"));
 /// ```
 pub fn get_context_envvars() -> Result<HashMap<String, String>, Error> {
-    EXTRACTOR
-        .lock()
-        .map_err(|e| Error::PoisonError(e.to_string()))?
-        .get(None, &Vec::new())
+    session::default().context_envvars()
+}
+
+/// Extract environment variables without shell context as they are (without converting
+/// into Unicode). `get_context_envvars` skips variables with a key or a value, which
+/// isn't valid Unicode; use `non_unicode` to find such variables.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, ffi::OsString};
+/// use envvars::get_context_envvars_os;
+///
+/// let vars: HashMap<OsString, OsString> = get_context_envvars_os().unwrap();
+///
+/// assert!(vars.contains_key(&OsString::from("PATH")) || vars.contains_key(&OsString::from("Path")));
+/// ```
+pub fn get_context_envvars_os() -> Result<HashMap<OsString, OsString>, Error> {
+    session::default().context_envvars_os()
+}
+
+/// Asynchronous version of `get_context_envvars`. Available with feature `async`.
+/// Dropping of the returned future kills the extractor process.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "async")]
+/// # tokio::runtime::Builder::new_current_thread()
+/// #     .enable_all()
+/// #     .build()
+/// #     .unwrap()
+/// #     .block_on(async {
+/// use std::collections::HashMap;
+/// use envvars::get_context_envvars_async;
+///
+/// let vars: HashMap<String, String> = get_context_envvars_async().await.unwrap();
+///
+/// assert!(vars.contains_key("PATH") || vars.contains_key("Path") || vars.contains_key("path"));
+/// # });
+/// ```
+#[cfg(feature = "async")]
+pub async fn get_context_envvars_async() -> Result<HashMap<String, String>, Error> {
+    session::default().context_envvars_async().await
+}
+
+/// Sets default timeout of extracting environment variables. It's used by
+/// `Profile::load` and `get_context_envvars`. If a shell doesn't finish in time
+/// (for example it waits for user input), it will be killed and `Error::Timeout`
+/// will be returned. By default timeout isn't set (`None`).
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use envvars::set_default_timeout;
+///
+/// set_default_timeout(Some(Duration::from_secs(5)));
+/// ```
+pub fn set_default_timeout(timeout: Option<Duration>) {
+    session::default().set_timeout(timeout);
+}
+
+/// Sets folder for the extractor of the default session. By default (`None`) the
+/// folder is taken from environment variable `ENVVARS_EXTRACTOR_DIR`; if it isn't
+/// defined, `envvars` tries `$XDG_RUNTIME_DIR/envvars` (unix), the cache folder of user
+/// (for example `~/.cache/envvars`) and a private folder of user in the system temporary
+/// folder (`/tmp/envvars-<uid>` on unix, `%TEMP%\envvars` on Windows). A folder is
+/// created with permissions `0o700`; an existing folder is used only if it belongs to
+/// the current user and cannot be changed by others (unix). The first folder, which
+/// allows executing files, is used.
+///
+/// # Examples
+///
+/// ```
+/// use std::env::temp_dir;
+/// use envvars::{get_context_envvars, set_extractor_dir};
+///
+/// set_extractor_dir(Some(temp_dir().join("my_app")));
+///
+/// assert!(get_context_envvars().is_ok());
+/// ```
+pub fn set_extractor_dir(dir: Option<PathBuf>) {
+    session::default().set_extractor_dir(dir);
+}
+
+/// Delivers the extractor of the default session and returns a guard, which removes
+/// the extractor from the disk as soon as it's dropped. Usually the guard is created at
+/// the start of an application and kept until its end.
+///
+/// Guards are counted: the extractor is removed only when the last guard is dropped
+/// (all guards of the default session in this process). Other processes are taken into
+/// account through file locks: while another process keeps its guard or runs a loading,
+/// the extractor isn't removed.
+///
+/// Without a guard the extractor stays on the disk to be reused by next runs (it can be
+/// removed manually with `cleanup()`).
+///
+/// # Examples
+///
+/// ```
+/// use envvars::{get_context_envvars, init};
+///
+/// let guard = init().unwrap();
+///
+/// assert!(get_context_envvars().is_ok());
+///
+/// // Extractor is removed
+/// drop(guard);
+/// ```
+pub fn init() -> Result<Guard, Error> {
+    session::default().guard()
 }
@@ -0,0 +1,176 @@
+use crate::Error;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+/// Environment variable to define a folder for the extractor
+pub(crate) const EXTRACTOR_DIR_ENVVAR: &str = "ENVVARS_EXTRACTOR_DIR";
+/// Name of subfolder used in shared locations (runtime and cache folders)
+const SUBFOLDER: &str = "envvars";
+
+fn from_envvar(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(unix)]
+fn runtime_dir() -> Option<PathBuf> {
+    from_envvar("XDG_RUNTIME_DIR").map(|dir| dir.join(SUBFOLDER))
+}
+
+#[cfg(not(unix))]
+fn runtime_dir() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(target_os = "macos")]
+fn cache_dir() -> Option<PathBuf> {
+    home::home_dir().map(|home| home.join("Library").join("Caches").join(SUBFOLDER))
+}
+
+#[cfg(windows)]
+fn cache_dir() -> Option<PathBuf> {
+    from_envvar("LOCALAPPDATA").map(|dir| dir.join(SUBFOLDER))
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn cache_dir() -> Option<PathBuf> {
+    from_envvar("XDG_CACHE_HOME")
+        .or_else(|| home::home_dir().map(|home| home.join(".cache")))
+        .map(|dir| dir.join(SUBFOLDER))
+}
+
+#[cfg(unix)]
+fn temp_dir() -> PathBuf {
+    // Safety: getuid is always successful
+    env::temp_dir().join(format!("{SUBFOLDER}-{}", unsafe { libc::getuid() }))
+}
+
+#[cfg(not(unix))]
+fn temp_dir() -> PathBuf {
+    env::temp_dir().join(SUBFOLDER)
+}
+
+/// Checks that the file belongs to the current user
+#[cfg(unix)]
+pub(crate) fn is_owned(metadata: &fs::Metadata) -> bool {
+    // Safety: getuid is always successful
+    metadata.uid() == unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_owned(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+/// Checks that the path belongs to the current user and cannot be changed by others
+#[cfg(unix)]
+fn verify(path: &Path, metadata: &fs::Metadata) -> Result<(), Error> {
+    // Safety: getuid is always successful
+    let uid = unsafe { libc::getuid() };
+    if metadata.uid() != uid {
+        return Err(Error::Insecure {
+            path: path.to_path_buf(),
+            reason: format!("owned by uid {} instead of {uid}", metadata.uid()),
+        });
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(Error::Insecure {
+            path: path.to_path_buf(),
+            reason: format!(
+                "writable by group or others (mode {:o})",
+                metadata.mode() & 0o777
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Creates (if needed) the folder for the extractor with permissions `0o700` and checks
+/// that the folder belongs to the current user and cannot be changed by others.
+#[cfg(unix)]
+pub(crate) fn secure_dir(dir: &Path) -> Result<(), Error> {
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(Error::create)?;
+    }
+    let metadata = fs::metadata(dir).map_err(Error::create)?;
+    if !metadata.is_dir() {
+        return Err(Error::create(io::Error::other(format!(
+            "{dir:?} isn't a folder"
+        ))));
+    }
+    verify(dir, &metadata)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn secure_dir(dir: &Path) -> Result<(), Error> {
+    if !dir.exists() {
+        fs::create_dir_all(dir).map_err(Error::create)?;
+    }
+    if !dir.is_dir() {
+        return Err(Error::create(io::Error::other(format!(
+            "{dir:?} isn't a folder"
+        ))));
+    }
+    Ok(())
+}
+
+/// Checks that the existing extractor is a regular file, which belongs to the current
+/// user and cannot be changed by others.
+#[cfg(unix)]
+pub(crate) fn secure_file(file: &Path) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(file).map_err(Error::create)?;
+    if !metadata.file_type().is_file() {
+        return Err(Error::Insecure {
+            path: file.to_path_buf(),
+            reason: String::from("isn't a regular file"),
+        });
+    }
+    verify(file, &metadata)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn secure_file(file: &Path) -> Result<(), Error> {
+    if !fs::symlink_metadata(file)
+        .map_err(Error::create)?
+        .file_type()
+        .is_file()
+    {
+        return Err(Error::Insecure {
+            path: file.to_path_buf(),
+            reason: String::from("isn't a regular file"),
+        });
+    }
+    Ok(())
+}
+
+/// Returns folders, which could be used for the extractor, in order of priority:
+/// - folder defined with `Envvars::set_extractor_dir`
+/// - folder defined with environment variable `ENVVARS_EXTRACTOR_DIR`
+/// - `$XDG_RUNTIME_DIR/envvars` (unix only)
+/// - cache folder of user (for example `~/.cache/envvars`)
+/// - private folder of user in the system temporary folder (for example
+///   `/tmp/envvars-1000`)
+///
+/// If a folder is defined explicitly (first two cases), it's the only candidate.
+pub(crate) fn candidates(configured: Option<&PathBuf>) -> Vec<PathBuf> {
+    if let Some(dir) = configured
+        .cloned()
+        .or_else(|| from_envvar(EXTRACTOR_DIR_ENVVAR))
+    {
+        return vec![dir];
+    }
+    [runtime_dir(), cache_dir(), Some(temp_dir())]
+        .into_iter()
+        .flatten()
+        .collect()
+}
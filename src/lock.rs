@@ -0,0 +1,155 @@
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions, TryLockError},
+    io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+
+/// How many times an attempt to lock is repeated, if the lock file has been removed
+/// by another process in between
+const ATTEMPTS: usize = 10;
+
+/// Path of file, which is used to lock deployment of the extractor between processes
+pub(crate) fn path(location: &Path) -> PathBuf {
+    let mut path = OsString::from(location.as_os_str());
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+#[cfg(unix)]
+fn open(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .mode(0o600)
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path) -> Result<File, io::Error> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Checks that the locked file is still available by the path. Another process could
+/// remove the lock file after it was opened, but before it was locked.
+#[cfg(unix)]
+fn is_actual(file: &File, path: &Path) -> Result<bool, io::Error> {
+    let locked = file.metadata()?;
+    Ok(match std::fs::metadata(path) {
+        Ok(current) => current.dev() == locked.dev() && current.ino() == locked.ino(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err),
+    })
+}
+
+#[cfg(not(unix))]
+fn is_actual(_file: &File, _path: &Path) -> Result<bool, io::Error> {
+    Ok(true)
+}
+
+/// Exclusive lock of deployment of the extractor. Works between threads and between
+/// processes (advisory file lock). Released on drop.
+pub(crate) struct Deployment {
+    file: File,
+    path: PathBuf,
+}
+
+impl Deployment {
+    /// Waits until the lock is acquired
+    pub(crate) fn acquire(location: &Path) -> Result<Self, io::Error> {
+        let path = path(location);
+        for _ in 0..ATTEMPTS {
+            let file = open(&path)?;
+            file.lock()?;
+            if is_actual(&file, &path)? {
+                return Ok(Deployment { file, path });
+            }
+        }
+        Err(io::Error::other(format!("Fail to lock {path:?}")))
+    }
+
+    /// Removes the lock file. The lock is kept until the end of removing, so other
+    /// processes will create a new lock file.
+    #[cfg(unix)]
+    pub(crate) fn remove(self) -> Result<(), io::Error> {
+        std::fs::remove_file(&self.path)?;
+        drop(self.file);
+        Ok(())
+    }
+
+    /// On Windows the lock file isn't removed: it cannot be removed while it's opened
+    /// by other processes.
+    #[cfg(not(unix))]
+    pub(crate) fn remove(self) -> Result<(), io::Error> {
+        drop(self.file);
+        Ok(())
+    }
+}
+
+/// Opens the extractor and locks it as used (shared lock). While at least one user
+/// keeps the lock, the extractor isn't removed. Released on drop.
+pub(crate) fn usage(location: &Path) -> Result<File, io::Error> {
+    let file = File::open(location)?;
+    file.lock_shared()?;
+    Ok(file)
+}
+
+/// Checks whether the extractor is used by someone (in this or another process)
+pub(crate) fn is_used(location: &Path) -> Result<bool, io::Error> {
+    let file = File::open(location)?;
+    match file.try_lock() {
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(err)) => Err(err),
+    }
+}
+
+/// Checks whether the file is opened or executed by any process of the current user
+/// (`/proc/<pid>/fd` and `/proc/<pid>/exe`). Used for files, which aren't locked by
+/// their users: extractors of previous versions and temporary files. Returns `None` if
+/// it cannot be checked.
+#[cfg(target_os = "linux")]
+pub(crate) fn is_open(location: &Path) -> Option<bool> {
+    let target = std::fs::metadata(location).ok()?;
+    let same = |path: &Path| {
+        std::fs::metadata(path)
+            .is_ok_and(|file| file.dev() == target.dev() && file.ino() == target.ino())
+    };
+    for process in std::fs::read_dir("/proc").ok()?.flatten() {
+        if !process
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|c| c.is_ascii_digit())
+        {
+            continue;
+        }
+        let process = process.path();
+        if same(&process.join("exe")) {
+            return Some(true);
+        }
+        // Processes of other users aren't accessible
+        let Ok(fds) = std::fs::read_dir(process.join("fd")) else {
+            continue;
+        };
+        if fds.flatten().any(|fd| same(&fd.path())) {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn is_open(_location: &Path) -> Option<bool> {
+    None
+}
@@ -0,0 +1,70 @@
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    path::PathBuf,
+    process::Command,
+    sync::Arc,
+};
+
+/// Anonymous sealed in-memory file with the extractor. Nothing is written on the disk
+/// and the sealed content cannot be changed, so exactly the embedded binary is
+/// executed.
+#[derive(Clone)]
+pub(crate) struct Memfd {
+    /// Read-only descriptor: an open writable descriptor would block executing with
+    /// `ETXTBSY`.
+    fd: Arc<OwnedFd>,
+}
+
+impl Memfd {
+    pub(crate) fn new(bin: &[u8]) -> Result<Self, io::Error> {
+        let name = CString::new("envvars_extractor").map_err(io::Error::other)?;
+        // Safety: name is a valid C string
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: fd is just created and isn't owned by anyone else
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(bin)?;
+        file.flush()?;
+        let seals =
+            libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+        // Safety: fd is valid as long as file is alive
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let readonly = File::open(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+        Ok(Memfd {
+            fd: Arc::new(OwnedFd::from(readonly)),
+        })
+    }
+
+    /// Path to execute the extractor. The path is resolved in the context of the
+    /// executing process (shell), which inherits the descriptor.
+    pub(crate) fn path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.fd.as_raw_fd()))
+    }
+
+    /// Makes the descriptor inheritable by the spawned process. In the parent process
+    /// the descriptor stays close-on-exec, so it isn't leaked into other processes.
+    pub(crate) fn inherit(&self, command: &mut Command) {
+        let fd = self.fd.clone();
+        // Safety: only async-signal-safe fcntl is called between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+}
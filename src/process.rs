@@ -0,0 +1,551 @@
+use crate::Error;
+#[cfg(unix)]
+use std::os::{
+    fd::{AsRawFd, OwnedFd},
+    unix::process::CommandExt,
+};
+use std::{
+    fs::File,
+    io::{self, Read},
+    process::{Child, Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(not(unix))]
+use std::{
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+/// How often the state of a child process is checked while waiting for it
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many times spawning is repeated if the executable is busy (`ETXTBSY`)
+pub(crate) const SPAWN_ATTEMPTS: usize = 5;
+
+/// How long pipes are still read after the shell has exited. Background processes
+/// started by the shell (`ssh-agent`, `tmux` etc.) could keep the pipes open for
+/// their whole life, so after this time pipes are abandoned.
+pub(crate) const GRACE_PERIOD: Duration = Duration::from_millis(200);
+
+/// Default limit of captured output (for each of stdout and stderr) in bytes
+pub(crate) const DEFAULT_MAX_OUTPUT: usize = 1024 * 1024;
+
+/// Whether the process has exited (false if it's still running) with its stdout and
+/// stderr
+type Captured = (bool, Vec<u8>, Vec<u8>);
+
+/// Spawns the command. Executing of just written file could fail with `ETXTBSY`, if
+/// another thread has been forking while the file was opened for writing; in this
+/// case spawning is repeated.
+fn spawn(command: &mut Command) -> Result<Child, io::Error> {
+    let mut attempt = 0;
+    loop {
+        match command.spawn() {
+            Err(err)
+                if err.kind() == io::ErrorKind::ExecutableFileBusy && attempt < SPAWN_ATTEMPTS =>
+            {
+                attempt += 1;
+                thread::sleep(POLL_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Options of running a process
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Options {
+    /// Timeout of running. `None` - no timeout
+    pub(crate) timeout: Option<Duration>,
+    /// Limit of captured output (for each of stdout and stderr) in bytes
+    pub(crate) max_output: usize,
+    /// Run the process under a pseudo-terminal (unix only)
+    pub(crate) terminal: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            timeout: None,
+            max_output: DEFAULT_MAX_OUTPUT,
+            terminal: false,
+        }
+    }
+}
+
+/// Prepares the command to be executed: stdin isn't available (the shell cannot wait
+/// for user input), stdout and stderr are captured. On unix the command gets its own
+/// session (and process group), so all processes started by the shell can be killed
+/// together. A new session detaches the shell from the controlling terminal of the
+/// application: an interactive shell in a background process group of the same
+/// session would stop itself (`SIGTTIN`) trying to take the terminal.
+///
+/// With `terminal` (unix only) the command is started in its own session under a new
+/// pseudo-terminal instead (see `pty::attach`); the master side of the terminal is
+/// returned to read stdout from.
+pub(crate) fn configure(command: &mut Command, terminal: bool) -> Result<Option<File>, io::Error> {
+    #[cfg(unix)]
+    if terminal {
+        return crate::pty::attach(command).map(Some);
+    }
+    #[cfg(not(unix))]
+    if terminal {
+        log::warn!("Pseudo-terminal isn't supported on this platform");
+    }
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Safety: only async-signal-safe setsid is called between fork and exec
+    #[cfg(unix)]
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(None)
+}
+
+/// Returns true if the error means, that the other side of the pipe is closed.
+/// Reading from the master side of a pseudo-terminal fails with `EIO` as soon as all
+/// processes have closed the terminal.
+pub(crate) fn is_hangup(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    return err.raw_os_error() == Some(libc::EIO);
+    #[cfg(not(unix))]
+    return err.kind() == io::ErrorKind::BrokenPipe;
+}
+
+/// Appends data to the buffer, but not more than `max` bytes in total. Returns false
+/// if some data has been discarded.
+pub(crate) fn append(buffer: &mut Vec<u8>, data: &[u8], max: usize) -> bool {
+    let free = max.saturating_sub(buffer.len());
+    buffer.extend_from_slice(&data[..data.len().min(free)]);
+    data.len() <= free
+}
+
+/// Kills all processes in the group of the shell (background processes started from
+/// its configuration files). The group could be already empty.
+#[cfg(unix)]
+pub(crate) fn kill_group(pid: u32) {
+    // Safety: killpg only sends a signal, an invalid group is reported as error
+    if unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } < 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            log::warn!("Fail to kill process group {pid}: {err}");
+        }
+    }
+}
+
+/// Kills the group of the shell on drop. Processes of the group are killed even if
+/// loading is interrupted (for example the future of asynchronous loading is dropped).
+/// The guard should be dropped before the shell is reaped: until then the id of the
+/// shell (and of its group) cannot be reused by another process.
+#[cfg(unix)]
+pub(crate) struct Group(pub(crate) u32);
+
+#[cfg(unix)]
+impl Drop for Group {
+    fn drop(&mut self) {
+        kill_group(self.0);
+    }
+}
+
+/// Returns true if the process has exited. Unlike `Child::try_wait` the process isn't
+/// reaped (`WNOWAIT`), so its id cannot be reused before its group is killed.
+#[cfg(unix)]
+pub(crate) fn has_exited(pid: u32) -> Result<bool, io::Error> {
+    loop {
+        // `si_signo` stays zero if the process is still running
+        // Safety: siginfo_t is a plain C structure, all zeros is a valid value
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // Safety: info is a valid pointer to siginfo_t for the duration of the call
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+            )
+        };
+        if result == 0 {
+            return Ok(info.si_signo != 0);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Kills child process and waits for it to be sure, no zombie process stays in the system
+fn terminate(child: &mut Child) {
+    if let Err(err) = child.kill() {
+        log::warn!("Fail to kill process {}: {err}", child.id());
+    }
+    if let Err(err) = child.wait() {
+        log::warn!("Fail to wait for process {}: {err}", child.id());
+    }
+}
+
+/// Output pipe of child process
+#[cfg(unix)]
+struct Pipe {
+    /// `None` as soon as the pipe is closed
+    file: Option<File>,
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+#[cfg(unix)]
+impl Pipe {
+    fn new<T: Into<OwnedFd>>(pipe: Option<T>) -> Result<Self, io::Error> {
+        let file = pipe.map(|pipe| File::from(pipe.into()));
+        if let Some(file) = file.as_ref() {
+            let fd = file.as_raw_fd();
+            // Safety: fd is a valid descriptor owned by file
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+            if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Pipe {
+            file,
+            data: Vec::new(),
+            truncated: false,
+        })
+    }
+
+    /// Reads all available data. Data over `max` bytes is discarded, but still read
+    /// to don't block the writer.
+    fn read(&mut self, max: usize) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        let mut chunk = [0; 8192];
+        loop {
+            match file.read(&mut chunk) {
+                Ok(0) => break self.file = None,
+                Ok(n) => self.truncated |= !append(&mut self.data, &chunk[..n], max),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if is_hangup(e) => break self.file = None,
+                Err(err) => {
+                    log::warn!("Fail to read output of process: {err}");
+                    break self.file = None;
+                }
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.file.is_none()
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        if self.truncated {
+            log::warn!(
+                "Output of process is truncated to {} bytes",
+                self.data.len()
+            );
+        }
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// Waits until some of the pipes has data (or is closed), but not longer than
+/// `timeout`
+#[cfg(unix)]
+fn wait(pipes: [&Pipe; 2], timeout: Duration) {
+    let mut fds = pipes
+        .iter()
+        .filter_map(|pipe| pipe.file.as_ref())
+        .map(|file| libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<libc::pollfd>>();
+    if fds.is_empty() {
+        return thread::sleep(timeout);
+    }
+    // Errors (like EINTR) aren't important here: the state is checked anyway
+    // Safety: fds is a valid array of fds.len() descriptors
+    unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            timeout.as_millis() as libc::c_int,
+        )
+    };
+}
+
+/// Reads output of the child until it exits (and a bit longer to get the rest of
+/// output). The child isn't reaped, so its group still can be killed safely.
+#[cfg(unix)]
+fn communicate(
+    child: &mut Child,
+    terminal: Option<File>,
+    deadline: Option<Instant>,
+    max_output: usize,
+) -> Result<Captured, io::Error> {
+    let mut stdout = match terminal {
+        Some(master) => Pipe::new(Some(master))?,
+        None => Pipe::new(child.stdout.take())?,
+    };
+    let mut stderr = Pipe::new(child.stderr.take())?;
+    let mut exited: Option<Instant> = None;
+    let exited = loop {
+        wait([&stdout, &stderr], POLL_INTERVAL);
+        stdout.read(max_output);
+        stderr.read(max_output);
+        if exited.is_none() && has_exited(child.id())? {
+            exited = Some(Instant::now());
+        }
+        if let Some(at) = exited {
+            if stdout.is_closed() && stderr.is_closed() {
+                break true;
+            }
+            if at.elapsed() >= GRACE_PERIOD {
+                log::debug!(
+                    "Output pipes of process {} are kept open by background processes",
+                    child.id()
+                );
+                break true;
+            }
+        } else if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break false;
+        }
+    };
+    Ok((exited, stdout.take(), stderr.take()))
+}
+
+#[cfg(not(unix))]
+type Buffer = Arc<Mutex<(Vec<u8>, bool)>>;
+
+/// Reads the given pipe in a separate thread. Collected data is available through
+/// the returned buffer at any moment, even if the pipe is still open.
+#[cfg(not(unix))]
+fn collect<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    max: usize,
+) -> (Buffer, Option<JoinHandle<()>>) {
+    let buffer: Buffer = Arc::new(Mutex::new((Vec::new(), false)));
+    let Some(mut pipe) = pipe else {
+        return (buffer, None);
+    };
+    let target = buffer.clone();
+    let handle = thread::spawn(move || {
+        let mut chunk = [0; 8192];
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if let Ok(mut buffer) = target.lock() {
+                        let (data, truncated) = &mut *buffer;
+                        *truncated |= !append(data, &chunk[..n], max);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    });
+    (buffer, Some(handle))
+}
+
+#[cfg(not(unix))]
+fn take(buffer: &Buffer) -> Vec<u8> {
+    buffer
+        .lock()
+        .map(|mut buffer| {
+            let (data, truncated) = &mut *buffer;
+            if *truncated {
+                log::warn!("Output of process is truncated to {} bytes", data.len());
+            }
+            std::mem::take(data)
+        })
+        .unwrap_or_default()
+}
+
+/// Reads output of the child until it exits (and a bit longer to get the rest of
+/// output). Readers, which are still blocked after the grace period, are left: they
+/// finish as soon as background processes close the pipes.
+#[cfg(not(unix))]
+fn communicate(
+    child: &mut Child,
+    _terminal: Option<File>,
+    deadline: Option<Instant>,
+    max_output: usize,
+) -> Result<Captured, io::Error> {
+    let (stdout, stdout_reader) = collect(child.stdout.take(), max_output);
+    let (stderr, stderr_reader) = collect(child.stderr.take(), max_output);
+    let exited = loop {
+        if child.try_wait()?.is_some() {
+            break true;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    if exited {
+        let exited = Instant::now();
+        let readers = [stdout_reader, stderr_reader];
+        while readers.iter().flatten().any(|reader| !reader.is_finished())
+            && exited.elapsed() < GRACE_PERIOD
+        {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    Ok((exited, take(&stdout), take(&stderr)))
+}
+
+/// Executes command and collects its output (not more than `max_output` bytes of each
+/// stream). If `timeout` is defined and the process is still running after it, the
+/// process will be killed and `Error::Timeout` with the output captured so far will
+/// be returned. See `Options` for other options.
+///
+/// The function doesn't wait for background processes started by the shell: as soon
+/// as the shell has exited, the rest of output is read during `GRACE_PERIOD` and all
+/// processes left in the shell's group are killed (unix). The group is killed before
+/// the shell is reaped, so its id cannot be taken by another process meanwhile.
+pub(crate) fn run(mut command: Command, options: &Options) -> Result<Output, Error> {
+    let terminal = configure(&mut command, options.terminal).map_err(Error::executing)?;
+    let mut child = spawn(&mut command).map_err(Error::executing)?;
+    // Command keeps the slave side of the terminal
+    drop(command);
+    #[cfg(unix)]
+    let group = Group(child.id());
+    let timeout = options.timeout;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let result = communicate(&mut child, terminal, deadline, options.max_output);
+    #[cfg(unix)]
+    drop(group);
+    match result {
+        Ok((true, stdout, stderr)) => Ok(Output {
+            status: child.wait().map_err(Error::executing)?,
+            stdout,
+            stderr,
+        }),
+        Ok((false, stdout, stderr)) => {
+            terminate(&mut child);
+            Err(Error::Timeout {
+                timeout: timeout.unwrap_or_default(),
+                stdout: String::from_utf8_lossy(&stdout).to_string(),
+                stderr: String::from_utf8_lossy(&stderr).to_string(),
+            })
+        }
+        Err(err) => {
+            terminate(&mut child);
+            Err(Error::executing(err))
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    fn timeout(timeout: Duration) -> Options {
+        Options {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test() {
+        let started = Instant::now();
+        match run(
+            sh("echo started; sleep 10"),
+            &timeout(Duration::from_millis(500)),
+        ) {
+            Err(Error::Timeout { stdout, .. }) => assert_eq!(stdout.trim(), "started"),
+            Err(err) => panic!("Expected timeout error, but got: {err}"),
+            Ok(_) => panic!("Expected timeout error, but process has been finished"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let output = run(Command::new("true"), &timeout(Duration::from_secs(5)))
+            .expect("Process should be finished");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn background() {
+        // Background process keeps stdout and stderr open
+        let started = Instant::now();
+        let output = run(
+            sh("sleep 30 & echo done"),
+            &timeout(Duration::from_secs(20)),
+        )
+        .expect("Process should be finished");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "done");
+        // Input isn't available
+        let output = run(
+            sh("read line; echo \"read: $?\""),
+            &timeout(Duration::from_secs(5)),
+        )
+        .expect("Process should be finished");
+        assert_ne!(String::from_utf8_lossy(&output.stdout).trim(), "read: 0");
+    }
+
+    #[test]
+    fn group() {
+        let mut child = sh("exit 3").spawn().expect("Process should be started");
+        let started = Instant::now();
+        while !has_exited(child.id()).expect("State should be checked") {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(POLL_INTERVAL);
+        }
+        // The process isn't reaped yet
+        let status = child.wait().expect("Process should be reaped");
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[test]
+    fn max_output() {
+        let options = Options {
+            max_output: 1000,
+            ..timeout(Duration::from_secs(5))
+        };
+        let output = run(sh("head -c 100000 /dev/zero; echo done >&2"), &options)
+            .expect("Process should be finished");
+        assert_eq!(output.stdout.len(), 1000);
+        assert_eq!(String::from_utf8_lossy(&output.stderr).trim(), "done");
+    }
+
+    #[test]
+    fn terminal() {
+        let script = "test -t 0 && test -t 1 && echo terminal; echo controlling > /dev/tty";
+        let output =
+            run(sh(script), &timeout(Duration::from_secs(5))).expect("Process should be finished");
+        assert_ne!(String::from_utf8_lossy(&output.stdout).trim(), "terminal");
+        let options = Options {
+            terminal: true,
+            ..timeout(Duration::from_secs(5))
+        };
+        let output = run(sh(script), &options).expect("Process should be finished");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "terminal\ncontrolling\n"
+        );
+        // Input isn't available under terminal either
+        let output = run(sh("read line; read line; echo \"read: $?\""), &options)
+            .expect("Process should be finished");
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "read: 1");
+    }
+}
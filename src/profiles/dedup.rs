@@ -0,0 +1,75 @@
+use crate::profiles::Profile;
+use std::{ffi::OsString, path::PathBuf};
+
+/// Key of shell: the canonical path of the executable file and the name, which it's
+/// invoked by. Multi-call binaries (like `busybox`) behave depending on the name, so
+/// `/bin/sh` and `/bin/ash` pointing to the same binary are different shells.
+fn key(profile: &Profile) -> (PathBuf, Option<OsString>) {
+    (
+        profile
+            .canonical_path
+            .clone()
+            .unwrap_or_else(|| profile.path.clone()),
+        profile.path.file_name().map(|name| name.to_os_string()),
+    )
+}
+
+/// Groups profiles of the same shell (for example `/bin/bash` and `/usr/bin/bash` on
+/// systems with merged `/usr`). The first profile of group is kept; paths of others are
+/// added into its `Profile::aliases`, as well as their default and parent marks.
+pub(crate) fn deduplicate(profiles: Vec<Profile>) -> Vec<Profile> {
+    let mut unique: Vec<Profile> = Vec::new();
+    for profile in profiles {
+        let key = key(&profile);
+        if let Some(first) = unique.iter_mut().find(|first| self::key(first) == key) {
+            first.is_default |= profile.is_default;
+            first.is_parent |= profile.is_parent;
+            first.aliases.push(profile.path);
+            first.aliases.extend(profile.aliases);
+        } else {
+            unique.push(profile);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::{fs, os::unix::fs::symlink};
+
+    #[test]
+    fn test() {
+        let dir = TempDir::new("dedup");
+        let real = dir.join("real");
+        let links = dir.join("links");
+        fs::create_dir_all(&real).expect("Folder should be created");
+        fs::create_dir_all(&links).expect("Folder should be created");
+        let busybox = real.join("busybox");
+        fs::write(&busybox, "").expect("File should be written");
+        // Multi-call binary: links with different names aren't duplicates
+        let sh = real.join("sh");
+        symlink(&busybox, &sh).expect("Symlink should be created");
+        symlink(&busybox, real.join("ash")).expect("Symlink should be created");
+        // The same shell in another folder is duplicate
+        symlink(&sh, links.join("sh")).expect("Symlink should be created");
+        let mut profiles = [real.join("sh"), real.join("ash"), links.join("sh")]
+            .iter()
+            .map(|path| Profile::new(path, vec!["-c"], None))
+            .collect::<Result<Vec<Profile>, _>>()
+            .expect("Profiles should be created");
+        profiles[2].is_default = true;
+        assert!(profiles
+            .iter()
+            .all(|profile| profile.canonical_path.as_ref() == Some(&busybox)));
+        let profiles = deduplicate(profiles);
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].path, real.join("sh"));
+        assert_eq!(profiles[0].aliases, vec![links.join("sh")]);
+        assert!(profiles[0].is_default);
+        assert_eq!(profiles[1].path, real.join("ash"));
+        assert!(profiles[1].aliases.is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    profiles::{
+        unix::{is_executable, SHELLS_FILE_PATH},
+        Profile, ProfileSource,
+    },
+    ShellKind,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Shells of service and locked accounts, which refuse to run anything
+const NO_LOGIN: &[&str] = &["nologin", "false", "true"];
+
+/// How many ancestors of the process are checked looking for the parent shell
+#[cfg(target_os = "linux")]
+const MAX_ANCESTORS: usize = 16;
+
+/// Returns the login shell of the current user from the account database
+#[cfg(unix)]
+fn account_shell() -> Option<PathBuf> {
+    use std::{
+        ffi::{CStr, OsStr},
+        mem,
+        os::unix::ffi::OsStrExt,
+        ptr,
+    };
+    // Safety: getuid is always successful
+    let uid = unsafe { libc::getuid() };
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        // Safety: passwd is a plain C structure, all zeros is a valid value
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result: *mut libc::passwd = ptr::null_mut();
+        // Safety: all pointers are valid for the duration of the call and the length of
+        // the buffer is passed
+        let code = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if code == libc::ERANGE && buffer.len() < 1024 * 1024 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if code != 0 || result.is_null() || passwd.pw_shell.is_null() {
+            return None;
+        }
+        // Safety: pw_shell isn't null and points to a null-terminated string in buffer
+        let shell = unsafe { CStr::from_ptr(passwd.pw_shell) }.to_bytes();
+        return (!shell.is_empty()).then(|| PathBuf::from(OsStr::from_bytes(shell)));
+    }
+}
+
+#[cfg(not(unix))]
+fn account_shell() -> Option<PathBuf> {
+    None
+}
+
+/// Returns id of the parent of the given process
+#[cfg(target_os = "linux")]
+fn parent_of(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The name of process is in brackets and could include spaces
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Walks up through ancestors of the process and returns the executable of the first
+/// known shell
+#[cfg(target_os = "linux")]
+fn find_parent_shell() -> Option<PathBuf> {
+    let mut pid = parent_of(std::process::id())?;
+    for _ in 0..MAX_ANCESTORS {
+        if pid <= 1 {
+            break;
+        }
+        if let Ok(exe) = fs::read_link(format!("/proc/{pid}/exe")) {
+            let kind = exe
+                .file_name()
+                .map(|name| ShellKind::from_name(&name.to_string_lossy()))
+                .unwrap_or_default();
+            if !matches!(kind, ShellKind::Unknown | ShellKind::Cmd) {
+                return Some(exe);
+            }
+        }
+        pid = parent_of(pid)?;
+    }
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn find_parent_shell() -> Option<PathBuf> {
+    None
+}
+
+/// true - if the account's shell is a real shell: it's an executable file and it's a
+/// known kind of shell or it's listed in `/etc/shells`. Shells of service and locked
+/// accounts (`nologin`, `false`) are never accepted.
+fn is_shell(shell: &Path) -> bool {
+    if !is_executable(shell) {
+        return false;
+    }
+    let name = shell
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if NO_LOGIN.contains(&name.as_str()) {
+        return false;
+    }
+    ShellKind::from_name(&name) != ShellKind::Unknown
+        || fs::read_to_string(SHELLS_FILE_PATH)
+            .map(|shells| shells.lines().any(|line| Path::new(line.trim()) == shell))
+            .unwrap_or(false)
+}
+
+/// Returns the default (login) shell of the current user. It's taken from the account
+/// database (`/etc/passwd`, LDAP etc.); if it isn't available there or it isn't a real
+/// shell (`/usr/sbin/nologin` of a service account, not a known shell and not listed
+/// in `/etc/shells`), from environment variable `SHELL`. Returns `None` on Windows.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(shell) = envvars::default_shell() {
+///     println!("Default shell: {shell:?}");
+/// }
+/// ```
+pub fn default_shell() -> Option<PathBuf> {
+    account_shell().filter(|shell| is_shell(shell)).or_else(|| {
+        env::var_os("SHELL")
+            .filter(|shell| !shell.is_empty())
+            .map(PathBuf::from)
+            .filter(|shell| is_shell(shell))
+    })
+}
+
+/// Returns the shell, which has launched the application (directly or through other
+/// processes, like `cargo run`). The shell is found by walking up through ancestors of
+/// the process (`/proc/<pid>/exe`), so it's available only on Linux.
+pub fn parent_shell() -> Option<PathBuf> {
+    find_parent_shell()
+}
+
+/// true - if both paths point to the same shell. Paths are compared as they are and,
+/// if names of files are equal, by the targets of symlinks (`/bin/bash` and
+/// `/usr/bin/bash`). Names are compared to keep multi-call binaries separated.
+fn same(profile: &Profile, path: &Path) -> bool {
+    if profile.path == path {
+        return true;
+    }
+    if profile.path.file_name() != path.file_name() {
+        return false;
+    }
+    match (fs::canonicalize(&profile.path), fs::canonicalize(path)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Marks profiles of the given shell (adds a profile, if the shell isn't in the list
+/// yet). Returns false if the profile cannot be created.
+fn mark(
+    profiles: &mut Vec<Profile>,
+    shell: &Path,
+    source: ProfileSource,
+    set: fn(&mut Profile),
+) -> bool {
+    let exact = profiles.iter().any(|profile| profile.path == shell);
+    let mut found = false;
+    for profile in profiles.iter_mut() {
+        if (exact && profile.path == shell) || (!exact && same(profile, shell)) {
+            set(profile);
+            found = true;
+        }
+    }
+    if found {
+        return true;
+    }
+    let kind = ShellKind::detect(shell);
+    match Profile::with_kind(
+        &shell.to_path_buf(),
+        Some(kind),
+        kind.command().to_vec(),
+        None,
+    ) {
+        Ok(mut profile) => {
+            profile.source = source;
+            set(&mut profile);
+            profiles.push(profile);
+            true
+        }
+        Err(err) => {
+            log::warn!("Cannot create profile for {shell:?}: {err}");
+            false
+        }
+    }
+}
+
+/// Marks the default shell and the parent shell in the list and sorts profiles: the
+/// default shell goes first, the parent shell goes next; other profiles keep their
+/// order. The default shell is ignored, if it isn't a real shell (see `is_shell`).
+pub(crate) fn apply(profiles: &mut Vec<Profile>, default: Option<&Path>, parent: Option<&Path>) {
+    if let Some(shell) = default.filter(|shell| is_shell(shell)) {
+        mark(profiles, shell, ProfileSource::Account, |profile| {
+            profile.is_default = true
+        });
+    }
+    if let Some(shell) = parent {
+        mark(profiles, shell, ProfileSource::Parent, |profile| {
+            profile.is_parent = true
+        });
+    }
+    profiles.sort_by_key(|profile| (!profile.is_default, !profile.is_parent));
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test() {
+        let mut profiles = ["/bin/sh", "/bin/bash"]
+            .iter()
+            .map(|path| Profile::new(&PathBuf::from(path), vec!["-c"], None))
+            .collect::<Result<Vec<Profile>, _>>()
+            .expect("Profiles should be created");
+        apply(
+            &mut profiles,
+            Some(Path::new("/bin/bash")),
+            Some(Path::new("/bin/sh")),
+        );
+        assert_eq!(profiles[0].path, Path::new("/bin/bash"));
+        assert!(profiles[0].is_default && !profiles[0].is_parent);
+        assert_eq!(profiles[1].path, Path::new("/bin/sh"));
+        assert!(profiles[1].is_parent && !profiles[1].is_default);
+        // Missing shell is added
+        let mut profiles = Vec::new();
+        apply(&mut profiles, Some(Path::new("/bin/sh")), None);
+        assert_eq!(profiles.len(), 1);
+        assert!(profiles[0].is_default);
+        assert_eq!(profiles[0].source, ProfileSource::Account);
+        // Shells of service accounts are ignored
+        let mut profiles = Vec::new();
+        apply(&mut profiles, Some(Path::new("/usr/sbin/nologin")), None);
+        apply(&mut profiles, Some(Path::new("/bin/false")), None);
+        assert!(profiles.is_empty());
+        assert!(!is_shell(Path::new("/sbin/nologin")));
+        assert!(is_shell(Path::new("/bin/sh")));
+        assert!(!is_shell(Path::new("/nonexistent/bash")));
+        // Known name isn't enough: the file should be executable
+        let dir = TempDir::new("is_shell");
+        fs::create_dir_all(dir.path()).expect("Folder should be created");
+        let file = dir.join("bash");
+        fs::write(&file, b"").expect("File should be written");
+        assert!(!is_shell(&file));
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o700))
+            .expect("Permissions should be set");
+        assert!(is_shell(&file));
+        assert!(!is_shell(dir.path()));
+        if let Some(shell) = default_shell() {
+            assert!(is_shell(&shell));
+        }
+    }
+}
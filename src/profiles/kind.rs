@@ -0,0 +1,183 @@
+use crate::LaunchMode;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Kind (family) of shell. The kind defines arguments of the shell: flags of each
+/// launch mode (see `ShellKind::flags`) and the way to pass a command (see
+/// `ShellKind::command`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShellKind {
+    /// GNU Bash (including `rbash`)
+    Bash,
+    /// Z shell
+    Zsh,
+    /// KornShell and its clones (`ksh93`, `mksh`, `oksh`, `pdksh`)
+    Ksh,
+    /// Debian Almquist shell
+    Dash,
+    /// Almquist shell (including `sh` of busybox)
+    Ash,
+    /// Generic POSIX shell, which doesn't support login mode
+    Sh,
+    /// C shell
+    Csh,
+    /// TENEX C shell
+    Tcsh,
+    /// Friendly interactive shell
+    Fish,
+    /// PowerShell (`pwsh`, version 6 and newer)
+    Pwsh,
+    /// Windows PowerShell (`powershell.exe`, version 5 and older)
+    PowerShell,
+    /// Nushell
+    Nushell,
+    /// Xonsh
+    Xonsh,
+    /// Elvish
+    Elvish,
+    /// Windows command prompt
+    Cmd,
+    /// Unknown shell. It's started without flags of launch mode and the command is
+    /// passed with `-c`.
+    #[default]
+    Unknown,
+}
+
+impl ShellKind {
+    /// Detects kind of shell by the name of its executable file (`bash`, `zsh.exe`,
+    /// `-bash` etc.)
+    pub fn from_name(name: &str) -> ShellKind {
+        let name = name.trim_start_matches('-').to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        match name {
+            "bash" | "rbash" => ShellKind::Bash,
+            "zsh" => ShellKind::Zsh,
+            "ksh" | "ksh93" | "mksh" | "oksh" | "pdksh" | "lksh" => ShellKind::Ksh,
+            "dash" => ShellKind::Dash,
+            "ash" => ShellKind::Ash,
+            "sh" | "posh" => ShellKind::Sh,
+            "csh" | "bsd-csh" => ShellKind::Csh,
+            "tcsh" => ShellKind::Tcsh,
+            "fish" => ShellKind::Fish,
+            "pwsh" | "pwsh-preview" => ShellKind::Pwsh,
+            "powershell" => ShellKind::PowerShell,
+            "nu" | "nushell" => ShellKind::Nushell,
+            "xonsh" => ShellKind::Xonsh,
+            "elvish" => ShellKind::Elvish,
+            "cmd" => ShellKind::Cmd,
+            _ => ShellKind::Unknown,
+        }
+    }
+
+    /// Detects kind of shell. The kind is detected by the name of the executable file;
+    /// if it isn't enough (the name isn't known or it's a generic `sh`), the target of
+    /// symlink is checked (for example `/bin/sh -> dash`). The shell isn't executed, so
+    /// a generic `sh`, which isn't a symlink, stays `ShellKind::Sh` (`Profile::probe`
+    /// detects the shell behind it).
+    pub fn detect(path: &Path) -> ShellKind {
+        let name = |path: &Path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        };
+        let kind = ShellKind::from_name(&name(path));
+        if !matches!(kind, ShellKind::Sh | ShellKind::Unknown) {
+            return kind;
+        }
+        if let Ok(target) = fs::canonicalize(path) {
+            let target = name(&target);
+            // Multi-call binary behaves as the applet, which it's called by
+            if target == "busybox" && kind == ShellKind::Sh {
+                return ShellKind::Ash;
+            }
+            let target = ShellKind::from_name(&target);
+            if !matches!(target, ShellKind::Sh | ShellKind::Unknown) {
+                return target;
+            }
+        }
+        kind
+    }
+
+    /// Returns flags of shell for the given launch mode. Modes, which aren't supported
+    /// by the shell, are falling back to the closest supported mode: for example `csh`
+    /// cannot be started as a login shell with a command, so it gets no flags in `Login`
+    /// mode.
+    pub fn flags(&self, mode: LaunchMode) -> &'static [&'static str] {
+        use LaunchMode::*;
+        match (self, mode) {
+            (ShellKind::Pwsh, Login | LoginInteractive) => &["-Login", "-NoLogo"],
+            (ShellKind::Pwsh | ShellKind::PowerShell, Interactive) => &["-NoLogo"],
+            (ShellKind::PowerShell, Login | LoginInteractive) => &["-NoLogo"],
+            (ShellKind::Pwsh | ShellKind::PowerShell, Plain) => {
+                &["-NoLogo", "-NoProfile", "-NonInteractive"]
+            }
+            (_, Plain) => &[],
+            (
+                ShellKind::Bash
+                | ShellKind::Zsh
+                | ShellKind::Ksh
+                | ShellKind::Dash
+                | ShellKind::Ash
+                | ShellKind::Fish
+                | ShellKind::Nushell
+                | ShellKind::Xonsh,
+                mode,
+            ) => match mode {
+                Login => &["-l"],
+                Interactive => &["-i"],
+                _ => &["-i", "-l"],
+            },
+            // `-l` isn't POSIX; csh cannot be a login shell and run a command
+            (ShellKind::Sh | ShellKind::Csh | ShellKind::Tcsh, Login) => &[],
+            (ShellKind::Sh | ShellKind::Csh | ShellKind::Tcsh, _) => &["-i"],
+            // Elvish reads its configuration only in interactive mode without command
+            (ShellKind::Elvish | ShellKind::Cmd | ShellKind::Unknown, _) => &[],
+        }
+    }
+
+    /// Returns arguments to pass a command into shell (like `-c`)
+    pub fn command(&self) -> &'static [&'static str] {
+        match self {
+            ShellKind::Pwsh | ShellKind::PowerShell => &["-Command"],
+            ShellKind::Cmd => &["/C"],
+            _ => &["-c"],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        assert_eq!(ShellKind::from_name("-bash"), ShellKind::Bash);
+        assert_eq!(
+            ShellKind::from_name("PowerShell.exe"),
+            ShellKind::PowerShell
+        );
+        assert_eq!(ShellKind::from_name("tmux"), ShellKind::Unknown);
+        let bash = ShellKind::Bash;
+        assert_eq!(bash.flags(LaunchMode::Login), ["-l"]);
+        assert_eq!(bash.flags(LaunchMode::Interactive), ["-i"]);
+        assert_eq!(bash.flags(LaunchMode::LoginInteractive), ["-i", "-l"]);
+        assert!(bash.flags(LaunchMode::Plain).is_empty());
+        assert!(ShellKind::Tcsh.flags(LaunchMode::Login).is_empty());
+        assert_eq!(ShellKind::Tcsh.flags(LaunchMode::LoginInteractive), ["-i"]);
+        assert!(ShellKind::Sh.flags(LaunchMode::Login).is_empty());
+        assert_eq!(
+            ShellKind::Pwsh.flags(LaunchMode::Login),
+            ["-Login", "-NoLogo"]
+        );
+        assert_eq!(ShellKind::Pwsh.command(), ["-Command"]);
+        assert!(ShellKind::Unknown
+            .flags(LaunchMode::LoginInteractive)
+            .is_empty());
+        #[cfg(unix)]
+        {
+            let sh = ShellKind::detect(Path::new("/bin/sh"));
+            assert_ne!(sh, ShellKind::Unknown);
+            assert_eq!(ShellKind::detect(Path::new("/bin/bash")), ShellKind::Bash);
+        }
+    }
+}
@@ -1,11 +1,24 @@
-use crate::{Error, EXTRACTOR};
+use crate::{decoder, session, Error, LoadReport};
+pub use default::{default_shell, parent_shell};
+pub use kind::ShellKind;
+pub use mode::LaunchMode;
+pub use probe::ShellInfo;
 use serde::{Deserialize, Serialize};
+pub use source::ProfileSource;
 use std::{
     collections::HashMap,
+    ffi::OsString,
     fs,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
+mod dedup;
+mod default;
+mod kind;
+mod mode;
+mod probe;
+mod source;
 pub mod unix;
 pub mod windows;
 
@@ -22,35 +35,91 @@ pub struct Profile {
     /// by default `envvars = None`. To load data should be used method `load`, which will
     /// make attempt to detect environment variables.
     pub envvars: Option<HashMap<String, String>>,
+    /// List of environment variables as they are (without converting into Unicode).
+    /// It's loaded together with `envvars`. Variables with a key or a value, which isn't
+    /// valid Unicode, are present only here (see `Profile::non_unicode`).
+    #[serde(default)]
+    pub envvars_os: Option<HashMap<OsString, OsString>>,
     /// true - if path to executable file of shell is symlink to another location.
     pub symlink: bool,
-    /// Private field to store arguments needed to execute shell in right way to grab list
-    /// of environment variables
-    args: Vec<String>,
+    /// Path to executable file of shell with all symlinks resolved. `None` if the path
+    /// cannot be resolved.
+    #[serde(default)]
+    pub canonical_path: Option<PathBuf>,
+    /// Other paths of the same shell (for example `/usr/bin/bash` for `/bin/bash`). Filled
+    /// only if profiles are detected with deduplication (see `DetectOptions`).
+    #[serde(default)]
+    pub aliases: Vec<PathBuf>,
+    /// Kind of shell. It's detected by the name of executable file (see
+    /// `ShellKind::detect`) and defines flags of each launch mode.
+    #[serde(default)]
+    pub kind: ShellKind,
+    /// true - if it's the default (login) shell of user (see `default_shell`)
+    #[serde(default)]
+    pub is_default: bool,
+    /// true - if the shell has launched the application (see `parent_shell`)
+    #[serde(default)]
+    pub is_parent: bool,
+    /// Where the profile has been discovered from
+    #[serde(default)]
+    pub source: ProfileSource,
+    /// Launch mode of shell used by `load`: `LaunchMode::LoginInteractive` for detected
+    /// profiles and `LaunchMode::Plain` for profiles created with `Profile::new`. It
+    /// defines flags of shell, like `-l` or `-i`; see `LaunchMode`.
+    #[serde(default)]
+    pub mode: LaunchMode,
+    /// Information about shell collected by `Profile::probe`. `None` if the shell
+    /// hasn't been probed yet.
+    #[serde(default)]
+    pub info: Option<ShellInfo>,
+    /// Private field to store arguments needed to pass a command into shell (without
+    /// flags of launch mode)
+    pub(crate) args: Vec<String>,
 }
 
 impl Profile {
     /// Creates shell's profile description
     /// * `shell` - path to shell's executable file
     /// * `args` - list of arguments needed to pass a command into shell. For example: "-c"
-    ///    to have a full command like: "/etc/bin/bash -c cmd"
+    ///   to have a full command like: "/etc/bin/bash -c cmd". The profile is created in
+    ///   `LaunchMode::Plain`, so bash-like shells are started with these arguments only;
+    ///   set `Profile::mode` to get flags of another launch mode (like "-l" or "-i")
+    ///   according to `Profile::kind` (see `ShellKind::command` for arguments of known
+    ///   shells)
     /// * `name` - optional name for profile. For unix based systems it will be name of
-    ///    executable file, like "bash", "fish" etc. For windows better to provide name to
-    ///    have it like "GitBash", "PowerShell" etc.
+    ///   executable file, like "bash", "fish" etc. For windows better to provide name to
+    ///   have it like "GitBash", "PowerShell" etc.
     pub fn new(shell: &PathBuf, args: Vec<&str>, name: Option<&str>) -> Result<Self, Error> {
+        let mut profile = Self::with_kind(shell, None, args, name)?;
+        profile.mode = LaunchMode::Plain;
+        Ok(profile)
+    }
+
+    /// Creates shell's profile description with already known kind of shell. If `kind`
+    /// isn't defined, it will be detected. Used for detected profiles, so the profile
+    /// gets the default launch mode.
+    pub(crate) fn with_kind(
+        shell: &PathBuf,
+        kind: Option<ShellKind>,
+        args: Vec<&str>,
+        name: Option<&str>,
+    ) -> Result<Self, Error> {
         let path = Path::new(shell);
         if !path.exists() {
-            return Err(Error::NotFound(shell.clone()));
+            return Err(Error::NotFound {
+                path: shell.clone(),
+            });
         }
+        let kind = kind.unwrap_or_else(|| ShellKind::detect(path));
         let symlink = fs::symlink_metadata(path)
-            .map_err(Error::Io)?
+            .map_err(Error::io)?
             .file_type()
             .is_symlink();
         let name = if let Some(name) = name {
             name.to_string()
         } else {
             path.file_name()
-                .ok_or(Error::Other(format!(
+                .ok_or(Error::other(format!(
                     "Found {shell:?}, but cannot convert path"
                 )))?
                 .to_string_lossy()
@@ -60,7 +129,16 @@ impl Profile {
             name,
             path: shell.clone(),
             envvars: None,
+            envvars_os: None,
             symlink,
+            canonical_path: fs::canonicalize(path).ok(),
+            aliases: Vec::new(),
+            kind,
+            source: ProfileSource::Manual,
+            is_default: false,
+            is_parent: false,
+            mode: LaunchMode::default(),
+            info: None,
             args: args
                 .into_iter()
                 .map(|s| s.to_owned())
@@ -68,9 +146,10 @@ impl Profile {
         })
     }
     /// Makes attempt to grab a list of environment variables for profile. It will
-    /// spawn an instance of shell with extractor as command argument. If stdout will
-    /// have suitable output, it will be parsed and list of environment variables will
-    /// be saved in `self.envvars`
+    /// spawn an instance of shell with extractor as command argument. If the extractor
+    /// will write suitable output into its private output file, it will be parsed and
+    /// list of environment variables will be saved in `self.envvars`. Returns the
+    /// report of loading (see `LoadReport`).
     ///
     /// # Examples
     ///
@@ -90,27 +169,181 @@ impl Profile {
     ///     "bash"
     /// });
     ///
-    /// profile.load().unwrap();
+    /// let report = profile.load().unwrap();
     ///
     /// assert!(profile.envvars.is_some());
+    /// println!("{} took {:?}; stderr: {}", report.command, report.duration, report.stderr);
     ///
     /// if let Some(vars) = profile.envvars.as_ref() {
     ///     assert!(vars.contains_key("PATH") || vars.contains_key("Path") || vars.contains_key("path"));
     /// }
     /// ```
-    pub fn load(&mut self) -> Result<(), Error> {
-        self.envvars = Some(
-            EXTRACTOR
-                .lock()
-                .map_err(|e| Error::PoisonError(e.to_string()))?
-                .get(Some(&self.path), &self.args)?,
-        );
-        Ok(())
+    pub fn load(&mut self) -> Result<LoadReport, Error> {
+        session::default().load_profile(self)
+    }
+
+    /// Asynchronous version of `load`. Available with feature `async`. Dropping of
+    /// the returned future kills the spawned shell.
+    #[cfg(feature = "async")]
+    pub async fn load_async(&mut self) -> Result<LoadReport, Error> {
+        let (envvars, report) = crate::asynchronous::get(
+            session::default().clone(),
+            Some(self.path.clone()),
+            self.arguments(self.mode),
+            self.mode,
+        )
+        .await?;
+        self.set_envvars(envvars);
+        Ok(report)
+    }
+
+    /// Same as `load`, but with the given timeout instead of the default one (see
+    /// `set_default_timeout`). If the shell doesn't finish in time, it will be killed
+    /// and `Error::Timeout` with captured stdout and stderr will be returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{path::PathBuf, str::FromStr, time::Duration};
+    /// use envvars::Profile;
+    ///
+    /// # if cfg!(unix) {
+    /// let mut profile =
+    ///     Profile::new(&PathBuf::from_str("/bin/sh").unwrap(), vec!["-c"], None).unwrap();
+    ///
+    /// profile.load_with_timeout(Duration::from_secs(10)).unwrap();
+    ///
+    /// assert!(profile.envvars.is_some());
+    /// # }
+    /// ```
+    pub fn load_with_timeout(&mut self, timeout: Duration) -> Result<LoadReport, Error> {
+        session::default().load_profile_with(self, Some(timeout), self.mode)
+    }
+
+    /// Same as `load`, but the shell is started in the given launch mode instead of
+    /// `Profile::mode`. The mode is recorded in the returned report.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::{path::PathBuf, str::FromStr};
+    /// use envvars::{LaunchMode, Profile};
+    ///
+    /// # if cfg!(unix) {
+    /// let mut profile =
+    ///     Profile::new(&PathBuf::from_str("/bin/sh").unwrap(), vec!["-c"], None).unwrap();
+    ///
+    /// let report = profile.load_with_mode(LaunchMode::Login).unwrap();
+    ///
+    /// assert_eq!(report.mode, LaunchMode::Login);
+    /// assert!(report.command.contains("\"-l\""));
+    /// # }
+    /// ```
+    pub fn load_with_mode(&mut self, mode: LaunchMode) -> Result<LoadReport, Error> {
+        session::default().load_profile_with_mode(self, mode)
+    }
+
+    /// Probes the shell without loading: gets its version, checks whether it can be
+    /// executed and whether it runs a command given as an argument (see `ShellInfo`).
+    /// The shell is started non-interactively (without configuration files, if the
+    /// shell allows it) with a short timeout, so probing is much faster than loading.
+    ///
+    /// The result is cached in `Profile::info`; to probe the shell again, reset it to
+    /// `None`. If the kind of profile is unknown or it's a generic `sh`, it's updated
+    /// with the kind detected by probing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use envvars::get_profiles;
+    ///
+    /// for mut profile in get_profiles().unwrap() {
+    ///     let info = profile.probe();
+    ///     if !info.accepts_command {
+    ///         println!("{} cannot be used to load envvars", profile.name);
+    ///     }
+    /// }
+    /// ```
+    pub fn probe(&mut self) -> &ShellInfo {
+        let info = match self.info.take() {
+            Some(info) => info,
+            None => {
+                let info = probe::probe(self);
+                if matches!(self.kind, ShellKind::Unknown | ShellKind::Sh) {
+                    self.kind = info.kind;
+                }
+                info
+            }
+        };
+        self.info.insert(info)
+    }
+
+    /// Returns keys of loaded environment variables, which are missing in `envvars`
+    /// because the key or the value isn't valid Unicode. Such variables are available
+    /// in `envvars_os`.
+    pub fn non_unicode(&self) -> Vec<&OsString> {
+        self.envvars_os
+            .as_ref()
+            .map(decoder::non_unicode)
+            .unwrap_or_default()
+    }
+
+    /// Returns arguments of shell for the given launch mode: flags of the mode followed
+    /// by arguments to pass a command
+    pub(crate) fn arguments(&self, mode: LaunchMode) -> Vec<String> {
+        self.kind
+            .flags(mode)
+            .iter()
+            .map(|flag| flag.to_string())
+            .chain(self.args.iter().cloned())
+            .collect()
     }
+
+    /// Stores loaded environment variables in both views
+    pub(crate) fn set_envvars(&mut self, envvars: HashMap<OsString, OsString>) {
+        self.envvars = Some(decoder::to_unicode(envvars.clone()));
+        self.envvars_os = Some(envvars);
+    }
+}
+
+/// Loads environment variables for all given profiles concurrently. The extractor is
+/// delivered once, after that shells are spawned in parallel, so loading of all
+/// profiles takes about as long as the slowest shell.
+/// * `profiles` - list of profiles to load
+/// * `concurrency` - max number of shells running at the same time; `0` - no limit
+///
+/// Returns an error if the extractor cannot be delivered. Otherwise returns a result
+/// of loading (the report or an error) for each profile (in the same order as profiles
+/// were given).
+///
+/// # Examples
+///
+/// ```
+/// use envvars::{get_profiles, load_all, Profile};
+///
+/// let mut profiles: Vec<Profile> = get_profiles().unwrap();
+///
+/// let results = load_all(&mut profiles, 4).unwrap();
+///
+/// profiles.iter().zip(results.iter()).for_each(|(profile, result)| {
+///     if let Err(err) = result {
+///         eprintln!("Cannot load envvars for {}: {err}", profile.name);
+///     }
+/// });
+/// ```
+pub fn load_all(
+    profiles: &mut [Profile],
+    concurrency: usize,
+) -> Result<Vec<Result<LoadReport, Error>>, Error> {
+    session::default().load_all(profiles, concurrency)
 }
 
 /// Returns all detected shell's profiles.
-/// - Unix based systems: reads /etc/shells and creates Profile for each found shell
+/// - Unix based systems: reads /etc/shells (if it exists) and looks for known shells in
+///   folders of `PATH` and in well-known installation prefixes (Homebrew, Nix,
+///   `~/.cargo/bin`, `~/.local/bin`, conda etc.); see `Profile::source`. The default
+///   shell of user (`Profile::is_default`) goes first, the shell, which has launched the
+///   application (`Profile::is_parent`), goes next
 /// - Windows: checks most regulars shells like CMD, PowerShell, GitBash, Cygwin etc.
 ///
 /// Because an operation of extracting of environment variables could take some time,
@@ -132,11 +365,70 @@ impl Profile {
 /// });
 /// ```
 pub fn get() -> Result<Vec<Profile>, Error> {
-    if cfg!(windows) {
-        windows::get()
+    get_with(DetectOptions::default())
+}
+
+/// Options of detecting shell's profiles
+#[derive(Debug, Clone, Default)]
+pub struct DetectOptions {
+    /// Group profiles of the same shell. Shells are the same if they have the same
+    /// canonical path (`Profile::canonical_path`) and are invoked by the same name: for
+    /// example `/bin/bash` and `/usr/bin/bash` on systems with merged `/usr`, but not
+    /// `/bin/sh` and `/bin/ash` pointing to `busybox`, because multi-call binaries behave
+    /// depending on the name. Only the first profile of group is returned; paths of
+    /// others are available in `Profile::aliases`.
+    pub deduplicate: bool,
+}
+
+/// Same as `get_profiles`, but with the given options
+///
+/// # Examples
+///
+/// ```
+/// use envvars::{get_profiles_with, DetectOptions};
+///
+/// let profiles = get_profiles_with(DetectOptions { deduplicate: true }).unwrap();
+///
+/// for profile in profiles.iter() {
+///     println!("{:?} (also {:?})", profile.path, profile.aliases);
+/// }
+/// ```
+pub fn get_with(options: DetectOptions) -> Result<Vec<Profile>, Error> {
+    let profiles = if cfg!(windows) {
+        windows::get()?
     } else if cfg!(unix) {
-        unix::get()
+        let mut profiles = unix::get()?;
+        default::apply(
+            &mut profiles,
+            default_shell().as_deref(),
+            parent_shell().as_deref(),
+        );
+        profiles
     } else {
-        Err(Error::NotSupportedPlatform)
+        return Err(Error::NotSupportedPlatform);
+    };
+    Ok(if options.deduplicate {
+        dedup::deduplicate(profiles)
+    } else {
+        profiles
+    })
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let shell = PathBuf::from("/bin/bash");
+        let profile =
+            Profile::new(&shell, vec!["-l", "-c"], None).expect("Profile should be created");
+        assert_eq!(profile.mode, LaunchMode::Plain);
+        assert_eq!(profile.arguments(profile.mode), ["-l", "-c"]);
+        let detected = Profile::with_kind(&shell, Some(ShellKind::Bash), vec!["-c"], None)
+            .expect("Profile should be created");
+        assert_eq!(detected.mode, LaunchMode::LoginInteractive);
+        assert_eq!(detected.arguments(detected.mode), ["-i", "-l", "-c"]);
     }
 }
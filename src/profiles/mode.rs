@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// The way of starting a shell. It defines which configuration files the shell reads
+/// before the extractor is executed, so it defines which environment variables are
+/// extracted.
+///
+/// Each kind of shell gets its own flags for each mode (see `ShellKind::flags`): for
+/// example `bash` is started with `-l` in `Login` mode and with `-i -l` in
+/// `LoginInteractive` mode. Shells, which don't support some mode (or aren't known),
+/// are started without additional flags.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LaunchMode {
+    /// Login shell (reads `.profile`, `.bash_profile`, `.zprofile` etc.)
+    Login,
+    /// Interactive shell (reads `.bashrc`, `.zshrc` etc.)
+    Interactive,
+    /// Interactive login shell. Reads the same files as a terminal emulator does, so
+    /// it's used for detected profiles.
+    #[default]
+    LoginInteractive,
+    /// Neither login nor interactive shell; only the command is passed
+    Plain,
+}
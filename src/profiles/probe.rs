@@ -0,0 +1,222 @@
+use crate::{decoder, process, profiles::Profile, Error, ShellKind};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::Path,
+    process::{Command, Output},
+    time::Duration,
+};
+
+/// Timeout of each probing run of shell (getting of version, checking of command,
+/// detecting of the shell behind a generic `sh`)
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Limit of captured output of probing run
+const PROBE_MAX_OUTPUT: usize = 4096;
+
+/// Prints the name of the shell, which is behind a generic `sh`
+const SH_SCRIPT: &str = "if [ -n \"$BASH_VERSION\" ]; then echo bash; \
+    elif [ -n \"$ZSH_VERSION\" ]; then echo zsh; \
+    elif [ -n \"$KSH_VERSION\" ]; then echo ksh; fi";
+
+/// Information about shell collected by `Profile::probe`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShellInfo {
+    /// Version string reported by shell (the first line of its output, for example
+    /// `GNU bash, version 5.2.15(1)-release`). `None` if the shell doesn't report its
+    /// version (like `dash`) or it cannot be run.
+    pub version: Option<String>,
+    /// true - if the shell can be executed
+    pub runnable: bool,
+    /// Kind of shell. It's refined by the version string, if it's possible (a generic
+    /// `sh` is checked with a short script instead); otherwise it's the kind of profile.
+    pub kind: ShellKind,
+    /// true - if the shell runs a command given as an argument (like `bash -c cmd`).
+    /// Only such shells can be used to load environment variables.
+    pub accepts_command: bool,
+}
+
+/// Returns flags, which prevent reading of configuration files by shell (where it's
+/// possible), so probing is fast and doesn't depend on configuration
+fn quiet(kind: ShellKind) -> &'static [&'static str] {
+    match kind {
+        ShellKind::Zsh | ShellKind::Csh | ShellKind::Tcsh => &["-f"],
+        ShellKind::Fish => &["--no-config"],
+        ShellKind::Pwsh | ShellKind::PowerShell => &["-NoLogo", "-NoProfile", "-NonInteractive"],
+        ShellKind::Nushell => &["--no-config-file"],
+        ShellKind::Xonsh => &["--no-rc"],
+        ShellKind::Elvish => &["-norc"],
+        _ => &[],
+    }
+}
+
+/// Returns arguments to get version of shell. `None` if the shell doesn't report its
+/// version.
+fn version(kind: ShellKind) -> Option<&'static [&'static str]> {
+    match kind {
+        ShellKind::Dash | ShellKind::Ash | ShellKind::Sh | ShellKind::Csh => None,
+        ShellKind::Elvish => Some(&["-version"]),
+        ShellKind::PowerShell => Some(&[
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "$PSVersionTable.PSVersion.ToString()",
+        ]),
+        ShellKind::Cmd => Some(&["/C", "ver"]),
+        _ => Some(&["--version"]),
+    }
+}
+
+/// Detects kind of shell by its version string
+fn from_version(version: &str) -> Option<ShellKind> {
+    let version = version.to_lowercase();
+    if version.contains("gnu bash") {
+        Some(ShellKind::Bash)
+    } else if version.starts_with("zsh ") {
+        Some(ShellKind::Zsh)
+    } else if version.starts_with("fish") {
+        Some(ShellKind::Fish)
+    } else if version.starts_with("tcsh") {
+        Some(ShellKind::Tcsh)
+    } else if version.starts_with("powershell") {
+        Some(ShellKind::Pwsh)
+    } else if version.starts_with("xonsh") {
+        Some(ShellKind::Xonsh)
+    } else if version.contains("ksh") || version.contains("version sh (at&t") {
+        Some(ShellKind::Ksh)
+    } else {
+        None
+    }
+}
+
+/// Runs shell with given arguments for probing: with a short timeout and a small limit
+/// of output. Returns `None` if the shell cannot be executed.
+fn run(shell: &Path, args: &[&str]) -> Option<Result<Output, Error>> {
+    let mut command = Command::new(shell);
+    command.args(args);
+    let options = process::Options {
+        timeout: Some(PROBE_TIMEOUT),
+        max_output: PROBE_MAX_OUTPUT,
+        terminal: false,
+    };
+    match process::run(command, &options) {
+        Err(Error::Executing { source }) => {
+            log::debug!("Fail to run {shell:?}: {source}");
+            None
+        }
+        result => Some(result),
+    }
+}
+
+/// Returns the first not empty line of output
+fn first_line(output: &Output) -> Option<String> {
+    [&output.stdout, &output.stderr]
+        .iter()
+        .flat_map(|data| {
+            String::from_utf8_lossy(data)
+                .lines()
+                .map(|line| line.trim().to_owned())
+                .collect::<Vec<String>>()
+        })
+        .find(|line| !line.is_empty())
+}
+
+/// Detects the shell behind a generic `sh` (for example `sh` of macOS is `bash` in
+/// POSIX mode) by variables like `BASH_VERSION`
+fn detect_sh(shell: &Path) -> Option<ShellKind> {
+    match run(shell, &["-c", SH_SCRIPT])? {
+        Ok(output) => Some(ShellKind::from_name(
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ))
+        .filter(|kind| *kind != ShellKind::Unknown),
+        Err(err) => {
+            log::debug!("Fail to detect shell behind {shell:?}: {err}");
+            None
+        }
+    }
+}
+
+/// Probes the shell: gets its version and checks whether it runs a command given as an
+/// argument. The shell is started non-interactively without configuration files (if
+/// the shell allows it) and with a short timeout.
+pub(crate) fn probe(profile: &Profile) -> ShellInfo {
+    let mut runnable = false;
+    let version = version(profile.kind).and_then(|args| {
+        let output = run(&profile.path, args)?;
+        runnable = true;
+        output
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| first_line(&output))
+    });
+    let token = format!("envvars_probe_{}", decoder::nonce());
+    let script = format!("echo {token}");
+    let mut args = quiet(profile.kind).to_vec();
+    args.extend(profile.args.iter().map(|arg| arg.as_str()));
+    args.push(&script);
+    let accepts_command = match run(&profile.path, &args) {
+        Some(output) => {
+            runnable = true;
+            output
+                .map(|output| String::from_utf8_lossy(&output.stdout).contains(&token))
+                .unwrap_or(false)
+        }
+        None => false,
+    };
+    let kind = version.as_deref().and_then(from_version).or_else(|| {
+        (profile.kind == ShellKind::Sh && runnable)
+            .then(|| detect_sh(&profile.path))
+            .flatten()
+    });
+    ShellInfo {
+        kind: kind.unwrap_or(profile.kind),
+        version,
+        runnable,
+        accepts_command,
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn test() {
+        let mut bash = Profile::new(&PathBuf::from("/bin/bash"), vec!["-c"], None)
+            .expect("Profile should be created");
+        let info = bash.probe().clone();
+        assert!(info.runnable);
+        assert!(info.accepts_command);
+        assert_eq!(info.kind, ShellKind::Bash);
+        assert!(info
+            .version
+            .as_deref()
+            .expect("Version should be reported")
+            .contains("bash"));
+        assert_eq!(bash.info, Some(info));
+        assert_eq!(quiet(ShellKind::Fish), ["--no-config"]);
+        // Kind of a generic `sh` is refined, if it's a known shell
+        let mut sh = Profile::new(&PathBuf::from("/bin/sh"), vec!["-c"], None)
+            .expect("Profile should be created");
+        sh.kind = ShellKind::Sh;
+        let info = sh.probe();
+        assert!(info.accepts_command);
+        assert_ne!(info.kind, ShellKind::Unknown);
+        // Executable, which ignores the command
+        let mut truth = Profile::new(&PathBuf::from("/bin/true"), vec!["-c"], None)
+            .expect("Profile should be created");
+        let info = truth.probe();
+        assert!(info.runnable);
+        assert!(!info.accepts_command);
+        // Not executable file
+        let file = TempDir::new("probe");
+        fs::write(file.path(), "").expect("File should be written");
+        let mut file = Profile::new(&file.path().to_path_buf(), vec!["-c"], None)
+            .expect("Profile should be created");
+        let info = file.probe();
+        assert!(!info.runnable);
+        assert!(!info.accepts_command);
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the shell's profile has been discovered from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ProfileSource {
+    /// The shell is listed in `/etc/shells`
+    ShellsFile,
+    /// The shell is found in one of folders of `PATH`
+    Path,
+    /// The shell is found in a well-known installation prefix (Homebrew, Nix, cargo,
+    /// conda etc.), which isn't in `PATH`
+    Prefix,
+    /// The shell is the default shell of user (see `default_shell`), which isn't found
+    /// in other sources
+    Account,
+    /// The shell has launched the application (see `parent_shell`), but it isn't found
+    /// in other sources
+    Parent,
+    /// The shell is found in its known location (Windows)
+    Known,
+    /// The profile has been created manually with `Profile::new`
+    #[default]
+    Manual,
+}
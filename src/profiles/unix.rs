@@ -1,46 +1,162 @@
-use crate::{profiles::Profile, Error};
-use is_terminal::IsTerminal;
-use std::{fs::read_to_string, path::Path};
+use crate::{
+    profiles::{Profile, ProfileSource},
+    Error, ShellKind,
+};
+use std::{
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
-const SHELLS_FILE_PATH: &str = "/etc/shells";
+pub(crate) const SHELLS_FILE_PATH: &str = "/etc/shells";
 
-pub(crate) fn get() -> Result<Vec<Profile>, Error> {
+/// Names of executable files of shells, which are looked for in `PATH` and in
+/// well-known installation prefixes
+const KNOWN_SHELLS: &[&str] = &[
+    "bash", "zsh", "fish", "ksh", "mksh", "oksh", "dash", "tcsh", "csh", "nu", "xonsh", "elvish",
+    "pwsh",
+];
+
+/// Well-known installation prefixes (relative paths are relative to the home folder)
+const PREFIXES: &[&str] = &[
+    "/home/linuxbrew/.linuxbrew/bin",
+    ".linuxbrew/bin",
+    "/opt/homebrew/bin",
+    "/usr/local/bin",
+    ".nix-profile/bin",
+    "/nix/var/nix/profiles/default/bin",
+    "/run/current-system/sw/bin",
+    ".cargo/bin",
+    ".local/bin",
+    "miniconda3/bin",
+    "anaconda3/bin",
+    "miniforge3/bin",
+    "/opt/conda/bin",
+    "/snap/bin",
+];
+
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Creates profile for the shell and adds it into the list, if it isn't there yet
+fn add(profiles: &mut Vec<Profile>, path: &Path, source: ProfileSource) {
+    if profiles.iter().any(|profile| profile.path == path) {
+        return;
+    }
+    let kind = ShellKind::detect(path);
+    match Profile::with_kind(
+        &path.to_path_buf(),
+        Some(kind),
+        kind.command().to_vec(),
+        None,
+    ) {
+        Ok(mut profile) => {
+            profile.source = source;
+            profiles.push(profile);
+        }
+        Err(err) => log::warn!("Cannot get envvars for {path:?}: {err}"),
+    }
+}
+
+/// Reads shells listed in `/etc/shells`. Missing file isn't an error: it's absent on
+/// some systems (for example in containers).
+fn shells_file(profiles: &mut Vec<Profile>) -> Result<(), Error> {
     let shells_file_path = Path::new(SHELLS_FILE_PATH);
     if !shells_file_path.exists() {
-        return Err(Error::NotFound(shells_file_path.to_path_buf()));
+        log::debug!("{SHELLS_FILE_PATH} doesn't exist");
+        return Ok(());
     }
-    let mut profiles: Vec<Profile> = vec![];
     for shell in read_to_string(shells_file_path)
-        .map_err(Error::Io)?
+        .map_err(Error::io)?
         .split('\n')
+        .map(|s| s.trim())
         .filter(|s| !s.starts_with('#') && !s.is_empty())
     {
-        let path = Path::new(shell);
-        let is_term = std::io::stdout().is_terminal();
-        let profile = match Profile::new(
-            &path.to_path_buf(),
-            if path.ends_with("tcsh") || path.ends_with("csh") {
-                if is_term {
-                    vec!["-c"]
-                } else {
-                    vec!["-ic"]
-                }
-            } else if is_term {
-                log::info!("TTY detected");
-                vec!["-l", "-c"]
+        add(profiles, Path::new(shell), ProfileSource::ShellsFile);
+    }
+    Ok(())
+}
+
+/// Looks for known shells in the given folders
+fn scan(profiles: &mut Vec<Profile>, dirs: &[PathBuf], source: ProfileSource) {
+    for dir in dirs.iter().filter(|dir| dir.is_absolute()) {
+        for name in KNOWN_SHELLS {
+            let path = dir.join(name);
+            if is_executable(&path) {
+                add(profiles, &path, source);
+            }
+        }
+    }
+}
+
+/// Returns folders of well-known installation prefixes (including the prefix of the
+/// active conda environment)
+fn prefixes() -> Vec<PathBuf> {
+    let home = home::home_dir();
+    let mut dirs = PREFIXES
+        .iter()
+        .filter_map(|prefix| {
+            let prefix = Path::new(prefix);
+            if prefix.is_absolute() {
+                Some(prefix.to_path_buf())
             } else {
-                log::info!("no TTY");
-                vec!["-i", "-l", "-c"]
-            },
-            None,
-        ) {
-            Ok(profile) => profile,
-            Err(err) => {
-                log::warn!("Cannot get envvars for {shell}: {err}");
-                continue;
+                home.as_ref().map(|home| home.join(prefix))
             }
-        };
-        profiles.push(profile);
+        })
+        .collect::<Vec<PathBuf>>();
+    if let Some(conda) = env::var_os("CONDA_PREFIX") {
+        dirs.push(PathBuf::from(conda).join("bin"));
     }
+    dirs
+}
+
+/// Returns shells listed in `/etc/shells`, shells found in folders of `PATH` and in
+/// well-known installation prefixes (in this order). Each shell is listed once.
+pub(crate) fn get() -> Result<Vec<Profile>, Error> {
+    let mut profiles: Vec<Profile> = vec![];
+    shells_file(&mut profiles)?;
+    let path = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect::<Vec<PathBuf>>())
+        .unwrap_or_default();
+    scan(&mut profiles, &path, ProfileSource::Path);
+    scan(&mut profiles, &prefixes(), ProfileSource::Prefix);
     Ok(profiles)
 }
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+    use crate::fixture::TempDir;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn test() {
+        let dir = TempDir::new("discovery");
+        fs::create_dir_all(dir.path()).expect("Folder should be created");
+        let fish = dir.join("fish");
+        fs::write(&fish, "#!/bin/sh\n").expect("File should be written");
+        fs::set_permissions(&fish, fs::Permissions::from_mode(0o755))
+            .expect("Permissions should be set");
+        // Not executable file is ignored
+        fs::write(dir.join("zsh"), "").expect("File should be written");
+        let dirs = vec![dir.path().to_path_buf()];
+        let mut profiles = Vec::new();
+        scan(&mut profiles, &dirs, ProfileSource::Prefix);
+        scan(&mut profiles, &dirs, ProfileSource::Path);
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].path, fish);
+        assert_eq!(profiles[0].kind, ShellKind::Fish);
+        assert_eq!(profiles[0].source, ProfileSource::Prefix);
+    }
+}
@@ -1,10 +1,12 @@
-use crate::{profiles::Profile, Error, EXTRACTOR};
+use crate::{
+    profiles::{LaunchMode, Profile, ProfileSource},
+    session, Error,
+};
 use home::home_dir;
 use std::{
     collections::HashMap,
     env,
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
 const WINDIR: &str = "windir";
@@ -13,11 +15,7 @@ const PROCESSOR_ARCHITEW6432: &str = "processor_architew6432";
 const HOMEDRIVE: &str = "homedrive";
 
 fn get_envvars() -> Result<HashMap<String, String>, Error> {
-    let envvars = match EXTRACTOR
-        .lock()
-        .map_err(|e| Error::PoisonError(e.to_string()))?
-        .get(None, &Vec::new())
-    {
+    let envvars = match session::default().context_envvars() {
         Ok(vars) => vars,
         Err(err) => {
             log::warn!("Fail to get envvars with extractor: {err}");
@@ -43,15 +41,19 @@ fn keys_to_lower_case(map: &HashMap<String, String>) -> HashMap<String, String>
     result
 }
 
-fn get_path_buf(str_path: &str) -> Result<PathBuf, Error> {
-    PathBuf::from_str(str_path).map_err(Error::Infallible)
-}
-
-fn add_profile(list: &mut Vec<Profile>, name: &str, path: PathBuf, args: Vec<&str>) {
+fn add_profile(
+    list: &mut Vec<Profile>,
+    name: &str,
+    path: PathBuf,
+    args: Vec<&str>,
+    mode: LaunchMode,
+) {
     if !path.exists() {
         return;
     }
-    if let Ok(profile) = Profile::new(&path, args, Some(name)) {
+    if let Ok(mut profile) = Profile::with_kind(&path, None, args, Some(name)) {
+        profile.mode = mode;
+        profile.source = ProfileSource::Known;
         list.push(profile);
     }
 }
@@ -61,10 +63,14 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
     let envvars_lower_case = keys_to_lower_case(&get_envvars()?);
     let windir = envvars_lower_case
         .get(WINDIR)
-        .ok_or(Error::NotFoundEnvVar(WINDIR.to_string()))?;
+        .ok_or(Error::NotFoundEnvVar {
+            name: WINDIR.to_string(),
+        })?;
     let homedrive = envvars_lower_case
         .get(HOMEDRIVE)
-        .ok_or(Error::NotFoundEnvVar(HOMEDRIVE.to_string()))?;
+        .ok_or(Error::NotFoundEnvVar {
+            name: HOMEDRIVE.to_string(),
+        })?;
     let system_32_path = if envvars_lower_case.contains_key(PROCESSOR_ARCHITEW6432) {
         Path::new(windir).join("Sysnative")
     } else {
@@ -83,6 +89,7 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
             "WSL",
             system_path.join("wsl.exe"),
             vec!["-c"],
+            LaunchMode::LoginInteractive,
         );
         // WSL Bash (build < 16299)
         add_profile(
@@ -90,6 +97,7 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
             "WSL (bash)",
             system_path.join("bash.exe"),
             vec!["-c"],
+            LaunchMode::LoginInteractive,
         );
     }
     // Windows PowerShell
@@ -101,6 +109,7 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
             .join("v1.0")
             .join("powershell.exe"),
         vec!["-c"],
+        LaunchMode::LoginInteractive,
     );
     if let Some(home) = home_dir() {
         // .NET Core PowerShell Global Tool
@@ -109,6 +118,7 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
             ".NET Core PowerShell Global Tool",
             home.join(".dotnet").join("tools").join("pwsh.exe"),
             vec!["-c"],
+            LaunchMode::LoginInteractive,
         );
     }
     // Command Prompt
@@ -117,36 +127,40 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
         "Command Prompt",
         system_32_path.join("cmd.exe"),
         vec![],
+        LaunchMode::LoginInteractive,
     );
     // Cygwin
     add_profile(
         &mut profiles,
         "Cygwin x64",
-        get_path_buf(homedrive)?
+        PathBuf::from(homedrive)
             .join("cygwin64")
             .join("bin")
             .join("bash.exe"),
-        vec!["--login", "-c"],
+        vec!["-c"],
+        LaunchMode::Login,
     );
     add_profile(
         &mut profiles,
         "Cygwin",
-        get_path_buf(homedrive)?
+        PathBuf::from(homedrive)
             .join("cygwin")
             .join("bin")
             .join("bash.exe"),
-        vec!["--login", "-c"],
+        vec!["-c"],
+        LaunchMode::Login,
     );
     // bash (MSYS2)
     add_profile(
         &mut profiles,
         "bash (MSYS2)",
-        get_path_buf(homedrive)?
+        PathBuf::from(homedrive)
             .join("msys64")
             .join("usr")
             .join("bin")
             .join("bash.exe"),
-        vec!["--login", "-i", "-c"],
+        vec!["-c"],
+        LaunchMode::LoginInteractive,
     );
     // GitBash
     for key in ["ProgramW6432", "ProgramFiles", "ProgramFiles(X86)"] {
@@ -154,18 +168,20 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
             add_profile(
                 &mut profiles,
                 "GitBash",
-                get_path_buf(v)?.join("Git").join("bin").join("bash.exe"),
-                vec!["--login", "-i", "-c"],
+                PathBuf::from(v).join("Git").join("bin").join("bash.exe"),
+                vec!["-c"],
+                LaunchMode::LoginInteractive,
             );
             add_profile(
                 &mut profiles,
                 "GitBash",
-                get_path_buf(v)?
+                PathBuf::from(v)
                     .join("Git")
                     .join("usr")
                     .join("bin")
                     .join("bash.exe"),
-                vec!["--login", "-i", "-c"],
+                vec!["-c"],
+                LaunchMode::LoginInteractive,
             );
         }
     }
@@ -173,26 +189,28 @@ pub(crate) fn get() -> Result<Vec<Profile>, Error> {
         add_profile(
             &mut profiles,
             "GitBash",
-            get_path_buf(v)?
+            PathBuf::from(v)
                 .join("Programs")
                 .join("Git")
                 .join("bin")
                 .join("bash.exe"),
-            vec!["--login", "-i", "-c"],
+            vec!["-c"],
+            LaunchMode::LoginInteractive,
         );
     }
     if let Some(v) = envvars.get("UserProfile") {
         add_profile(
             &mut profiles,
             "GitBash",
-            get_path_buf(v)?
+            PathBuf::from(v)
                 .join("scoop")
                 .join("apps")
                 .join("git-with-openssh")
                 .join("current")
                 .join("bin")
                 .join("bash.exe"),
-            vec!["--login", "-i", "-c"],
+            vec!["-c"],
+            LaunchMode::LoginInteractive,
         );
     }
     Ok(profiles)
@@ -0,0 +1,126 @@
+use std::{
+    ffi::{CStr, OsStr},
+    fs::{File, OpenOptions},
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt, process::CommandExt},
+    },
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// Size of the terminal reported to the shell
+const ROWS: u16 = 24;
+const COLUMNS: u16 = 80;
+
+fn check(result: libc::c_int) -> Result<libc::c_int, io::Error> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Returns path of the slave side of the terminal
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn name(master: RawFd) -> Result<PathBuf, io::Error> {
+    let mut buffer = [0 as libc::c_char; 128];
+    // Safety: the buffer is valid for its whole length during the call
+    let code = unsafe { libc::ptsname_r(master, buffer.as_mut_ptr(), buffer.len()) };
+    if code != 0 {
+        return Err(io::Error::from_raw_os_error(code));
+    }
+    // Safety: on success ptsname_r writes a null-terminated string into the buffer
+    let name = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    Ok(PathBuf::from(OsStr::from_bytes(name.to_bytes())))
+}
+
+/// Returns path of the slave side of the terminal. `ptsname` uses a static buffer, so
+/// calls are serialized.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn name(master: RawFd) -> Result<PathBuf, io::Error> {
+    static PTSNAME: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _lock = PTSNAME
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    // Safety: the static buffer of ptsname is protected by the lock
+    let name = unsafe { libc::ptsname(master) };
+    if name.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: name isn't null and points to a null-terminated string
+    let name = unsafe { CStr::from_ptr(name) };
+    Ok(PathBuf::from(OsStr::from_bytes(name.to_bytes())))
+}
+
+/// Opens a new pseudo-terminal. Returns its master and slave sides. Output processing
+/// is disabled, so the shell's output comes as it is (without `\r\n` instead of `\n`).
+/// Input is switched to non-canonical mode without minimal count of bytes and without
+/// timeout: nothing is ever written to the master side, so reading from the terminal
+/// returns end of file immediately instead of waiting for user input (like reading of
+/// `/dev/null`).
+fn open() -> Result<(File, File), io::Error> {
+    // Safety: the descriptor is just opened and isn't owned by anything else
+    let master =
+        unsafe { OwnedFd::from_raw_fd(check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?) };
+    let fd = master.as_raw_fd();
+    // Safety: fd is a valid descriptor owned by master
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    check(unsafe { libc::grantpt(fd) })?;
+    check(unsafe { libc::unlockpt(fd) })?;
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(name(fd)?)?;
+    // Safety: termios is a plain C structure, all zeros is a valid value; it's filled
+    // by tcgetattr before using
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    check(unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) })?;
+    termios.c_oflag &= !libc::OPOST;
+    termios.c_lflag &= !libc::ICANON;
+    termios.c_cc[libc::VMIN] = 0;
+    termios.c_cc[libc::VTIME] = 0;
+    check(unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) })?;
+    let size = libc::winsize {
+        ws_row: ROWS,
+        ws_col: COLUMNS,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // Safety: size is a valid winsize for the duration of the call
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ as _, &size) } < 0 {
+        log::debug!(
+            "Fail to set size of terminal: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok((File::from(master), slave))
+}
+
+/// Runs the command under a new pseudo-terminal. The command gets its own session
+/// with the terminal as the controlling one; stdin (always at end of file, see `open`)
+/// and stdout are connected to the terminal, stderr is captured with a pipe. Returns
+/// the master side of the terminal to read stdout from.
+///
+/// The command keeps the slave side of the terminal open, so it should be dropped as
+/// soon as the process is spawned; otherwise closing of the terminal isn't detected.
+pub(crate) fn attach(command: &mut Command) -> Result<File, io::Error> {
+    let (master, slave) = open()?;
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave))
+        .stderr(Stdio::piped());
+    // Safety: the closure calls only async-signal-safe functions (setsid and ioctl)
+    unsafe {
+        command.pre_exec(|| {
+            // Session (and its process group) gets the id of the shell, so the shell
+            // and its background processes still can be killed together
+            check(libc::setsid())?;
+            check(libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0))?;
+            Ok(())
+        });
+    }
+    Ok(master)
+}
@@ -0,0 +1,64 @@
+use crate::{decoder, LaunchMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    process::{Command, Output},
+    time::Duration,
+};
+
+/// Report of loading environment variables. Describes what happened during loading;
+/// could be used to diagnose shells, which don't work as expected.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoadReport {
+    /// Command line, which has been executed (the shell with its arguments and the
+    /// path to the extractor)
+    pub command: String,
+    /// Launch mode of the shell (`LaunchMode::Plain` if environment variables have
+    /// been extracted without shell)
+    #[serde(default)]
+    pub mode: LaunchMode,
+    /// Exit code of the shell. `None` if the shell has been terminated by a signal
+    pub code: Option<i32>,
+    /// true - if the shell has exited successfully
+    pub success: bool,
+    /// Duration of loading (from spawning of the shell until its exit)
+    pub duration: Duration,
+    /// Everything printed by the shell (its configuration files) into stdout
+    pub stdout: String,
+    /// Everything printed by the shell (its configuration files) into stderr
+    pub stderr: String,
+    /// Keys of environment variables, which are missing in `String` view, because
+    /// the key or the value isn't valid Unicode
+    pub dropped: Vec<OsString>,
+}
+
+impl LoadReport {
+    pub(crate) fn new(
+        command: String,
+        mode: LaunchMode,
+        output: &Output,
+        duration: Duration,
+        envvars: &HashMap<OsString, OsString>,
+    ) -> Self {
+        LoadReport {
+            command,
+            mode,
+            code: output.status.code(),
+            success: output.status.success(),
+            duration,
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            dropped: decoder::non_unicode(envvars).into_iter().cloned().collect(),
+        }
+    }
+}
+
+/// Returns command line of command as it would be written in a shell
+pub(crate) fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| format!("{arg:?}"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
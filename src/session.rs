@@ -0,0 +1,474 @@
+use crate::{
+    decoder,
+    extractor::{self, Executable, ExecutionMode, Extractor, Invocation},
+    process,
+    profiles::Profile,
+    report::LoadReport,
+    Error, LaunchMode,
+};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+lazy_static! {
+    #[doc(hidden)]
+    static ref DEFAULT: Envvars = Envvars::new();
+}
+
+/// Session used by free functions like `get_context_envvars` and by `Profile::load`
+pub(crate) fn default() -> &'static Envvars {
+    &DEFAULT
+}
+
+/// Locks the mutex ignoring poisoning. The state behind session's locks stays valid
+/// even if some holder has panicked, so a panic in one caller doesn't break others.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Settings of session
+#[derive(Debug)]
+struct Settings {
+    /// Timeout of extracting. `None` - no timeout
+    timeout: Option<Duration>,
+    /// Limit of captured output of shell (for each of stdout and stderr) in bytes
+    max_output: usize,
+    /// Run shell under a pseudo-terminal
+    pseudo_terminal: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            timeout: None,
+            max_output: process::DEFAULT_MAX_OUTPUT,
+            pseudo_terminal: false,
+        }
+    }
+}
+
+struct Inner {
+    extractor: Mutex<Extractor>,
+    settings: Mutex<Settings>,
+    /// Count of alive guards of session
+    guards: AtomicUsize,
+}
+
+/// Session of `envvars`. Owns the extractor and settings of extracting. All
+/// free functions (`get_context_envvars`, `load_all`, `Profile::load` etc.) are using
+/// a default session; an own session could be created to have independent settings.
+///
+/// Session is cheap to clone: clones are sharing the same extractor and settings.
+///
+/// # Examples
+///
+/// ```
+/// use std::{collections::HashMap, time::Duration};
+/// use envvars::{get_profiles, Envvars};
+///
+/// let session = Envvars::new();
+/// session.set_timeout(Some(Duration::from_secs(10)));
+///
+/// let vars: HashMap<String, String> = session.context_envvars().unwrap();
+/// assert!(vars.contains_key("PATH") || vars.contains_key("Path") || vars.contains_key("path"));
+///
+/// for profile in get_profiles().unwrap() {
+///     if let Err(err) = session.load(&profile) {
+///         eprintln!("Cannot load envvars for {}: {err}", profile.name);
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Envvars {
+    inner: Arc<Inner>,
+}
+
+impl Envvars {
+    /// Creates a new session with default settings
+    pub fn new() -> Self {
+        Envvars {
+            inner: Arc::new(Inner {
+                extractor: Mutex::new(Extractor::new()),
+                settings: Mutex::new(Settings::default()),
+                guards: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Sets timeout of extracting environment variables. If a shell doesn't finish in
+    /// time (for example it waits for user input), it will be killed and
+    /// `Error::Timeout` will be returned. By default timeout isn't set (`None`).
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        lock(&self.inner.settings).timeout = timeout;
+    }
+
+    /// Returns current timeout of extracting
+    pub fn timeout(&self) -> Option<Duration> {
+        lock(&self.inner.settings).timeout
+    }
+
+    /// Sets limit of captured output of shell in bytes (for each of stdout and
+    /// stderr; 1 MiB by default). Output is used only for diagnostics (`LoadReport`,
+    /// `Error::Parsing`, `Error::Timeout`), so the rest of output is discarded.
+    pub fn set_max_output(&self, max_output: usize) {
+        lock(&self.inner.settings).max_output = max_output;
+    }
+
+    /// Returns current limit of captured output of shell
+    pub fn max_output(&self) -> usize {
+        lock(&self.inner.settings).max_output
+    }
+
+    /// Enables running of shell under a pseudo-terminal (unix only; ignored on other
+    /// platforms). By default the shell is started without terminal, so results could
+    /// depend on sections of configuration files, which are guarded by TTY checks. In
+    /// this mode the shell gets a new pseudo-terminal as its controlling terminal
+    /// (`stdin` and `stdout`) regardless of how the application was started; `stderr`
+    /// is still captured separately. Reading from the terminal returns end of file
+    /// immediately, so the shell still cannot wait for user input.
+    pub fn set_pseudo_terminal(&self, enabled: bool) {
+        lock(&self.inner.settings).pseudo_terminal = enabled;
+    }
+
+    /// Returns true if shell is started under a pseudo-terminal
+    pub fn pseudo_terminal(&self) -> bool {
+        lock(&self.inner.settings).pseudo_terminal
+    }
+
+    /// Options of running shell with the given timeout
+    pub(crate) fn options(&self, timeout: Option<Duration>) -> process::Options {
+        let settings = lock(&self.inner.settings);
+        process::Options {
+            timeout,
+            max_output: settings.max_output,
+            terminal: settings.pseudo_terminal,
+        }
+    }
+
+    /// Sets folder for the extractor. By default (`None`) the folder is taken from
+    /// environment variable `ENVVARS_EXTRACTOR_DIR`; if it isn't defined, `envvars`
+    /// tries `$XDG_RUNTIME_DIR/envvars` (unix), the cache folder of user (for example
+    /// `~/.cache/envvars`) and a private folder of user in the system temporary folder
+    /// (`/tmp/envvars-<uid>` on unix, `%TEMP%\envvars` on Windows). A folder is created
+    /// with permissions `0o700`; an existing folder is used only if it belongs to the
+    /// current user and cannot be changed by others (unix). The first folder, which
+    /// allows executing files, is used. If none of them fits, `Error::NoLocation` with
+    /// all tried folders is returned.
+    pub fn set_extractor_dir(&self, dir: Option<PathBuf>) {
+        lock(&self.inner.extractor).set_dir(dir);
+    }
+
+    /// Sets the way of executing the extractor (`ExecutionMode::File` by default). See
+    /// `ExecutionMode` for details.
+    pub fn set_execution_mode(&self, mode: ExecutionMode) {
+        lock(&self.inner.extractor).set_mode(mode);
+    }
+
+    /// Enables strict verification of the extractor. By default the checksum of the
+    /// extractor is checked only if its file has been changed (device, inode, size or
+    /// modification time) since the last verification. In strict mode the checksum is
+    /// checked before each using.
+    pub fn set_strict_verification(&self, strict: bool) {
+        lock(&self.inner.extractor).set_strict(strict);
+    }
+
+    /// Delivers the extractor and creates a command to run it. The extractor is locked
+    /// only while delivering, so a running shell doesn't block other loadings. The
+    /// returned `Executable` should be kept alive until the command is finished: it
+    /// protects the extractor from removing.
+    pub(crate) fn prepare(
+        &self,
+        shell: Option<&PathBuf>,
+        args: &[String],
+        mode: LaunchMode,
+    ) -> Result<(Executable, Invocation), Error> {
+        let executable = lock(&self.inner.extractor).prepare()?;
+        let invocation = extractor::command(&executable, shell, args, mode)?;
+        Ok((executable, invocation))
+    }
+
+    /// Extracts environment variables without shell context. Variables with a key or a
+    /// value, which isn't valid Unicode, are skipped; see `context_envvars_os`.
+    pub fn context_envvars(&self) -> Result<HashMap<String, String>, Error> {
+        self.context_envvars_os().map(decoder::to_unicode)
+    }
+
+    /// Extracts environment variables without shell context as they are (without
+    /// converting into Unicode).
+    pub fn context_envvars_os(&self) -> Result<HashMap<OsString, OsString>, Error> {
+        let (_executable, invocation) = self.prepare(None, &[], LaunchMode::Plain)?;
+        extractor::run(invocation, &self.options(self.timeout())).map(|(envvars, _)| envvars)
+    }
+
+    /// Extracts environment variables of the given shell's profile. The profile
+    /// itself isn't changed; see `Profile::load` to store results in the profile.
+    /// Variables with a key or a value, which isn't valid Unicode, are skipped; see
+    /// `load_os`.
+    pub fn load(&self, profile: &Profile) -> Result<HashMap<String, String>, Error> {
+        self.load_with_timeout(profile, self.timeout())
+    }
+
+    /// Same as `load`, but with the given timeout instead of the session's one
+    pub fn load_with_timeout(
+        &self,
+        profile: &Profile,
+        timeout: Option<Duration>,
+    ) -> Result<HashMap<String, String>, Error> {
+        self.load_os_with_timeout(profile, timeout)
+            .map(decoder::to_unicode)
+    }
+
+    /// Extracts environment variables of the given shell's profile as they are
+    /// (without converting into Unicode).
+    pub fn load_os(&self, profile: &Profile) -> Result<HashMap<OsString, OsString>, Error> {
+        self.load_os_with_timeout(profile, self.timeout())
+    }
+
+    /// Same as `load_os`, but with the given timeout instead of the session's one
+    pub fn load_os_with_timeout(
+        &self,
+        profile: &Profile,
+        timeout: Option<Duration>,
+    ) -> Result<HashMap<OsString, OsString>, Error> {
+        self.extract(profile, timeout, profile.mode)
+            .map(|(envvars, _)| envvars)
+    }
+
+    /// Loads environment variables of the given shell's profile and stores them in the
+    /// profile (`Profile::envvars` and `Profile::envvars_os`). Returns the report of
+    /// loading: executed command line, exit status, duration, output of the shell and
+    /// skipped variables. The shell is started in the launch mode of the profile
+    /// (`Profile::mode`).
+    pub fn load_profile(&self, profile: &mut Profile) -> Result<LoadReport, Error> {
+        self.load_profile_with(profile, self.timeout(), profile.mode)
+    }
+
+    /// Same as `load_profile`, but the shell is started in the given launch mode
+    /// instead of the profile's one
+    pub fn load_profile_with_mode(
+        &self,
+        profile: &mut Profile,
+        mode: LaunchMode,
+    ) -> Result<LoadReport, Error> {
+        self.load_profile_with(profile, self.timeout(), mode)
+    }
+
+    pub(crate) fn load_profile_with(
+        &self,
+        profile: &mut Profile,
+        timeout: Option<Duration>,
+        mode: LaunchMode,
+    ) -> Result<LoadReport, Error> {
+        let (envvars, report) = self.extract(profile, timeout, mode)?;
+        profile.set_envvars(envvars);
+        Ok(report)
+    }
+
+    fn extract(
+        &self,
+        profile: &Profile,
+        timeout: Option<Duration>,
+        mode: LaunchMode,
+    ) -> Result<(HashMap<OsString, OsString>, LoadReport), Error> {
+        let (_executable, invocation) =
+            self.prepare(Some(&profile.path), &profile.arguments(mode), mode)?;
+        extractor::run(invocation, &self.options(timeout))
+    }
+
+    /// Loads environment variables for all given profiles concurrently. See
+    /// `envvars::load_all` for details.
+    pub fn load_all(
+        &self,
+        profiles: &mut [Profile],
+        concurrency: usize,
+    ) -> Result<Vec<Result<LoadReport, Error>>, Error> {
+        let executable = lock(&self.inner.extractor).prepare()?;
+        let invocations = profiles
+            .iter()
+            .map(|profile| {
+                extractor::command(
+                    &executable,
+                    Some(&profile.path),
+                    &profile.arguments(profile.mode),
+                    profile.mode,
+                )
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let options = self.options(self.timeout());
+        let workers = if concurrency == 0 {
+            profiles.len()
+        } else {
+            concurrency.min(profiles.len())
+        };
+        let jobs = Mutex::new(profiles.iter_mut().zip(invocations).enumerate());
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let Some((n, (profile, invocation))) = lock(&jobs).next() else {
+                        break;
+                    };
+                    let result = extractor::run(invocation, &options).map(|(envvars, report)| {
+                        profile.set_envvars(envvars);
+                        report
+                    });
+                    lock(&results).push((n, result));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap_or_else(PoisonError::into_inner);
+        results.sort_by_key(|(n, _)| *n);
+        Ok(results.into_iter().map(|(_, result)| result).collect())
+    }
+
+    /// Asynchronous version of `context_envvars`. Available with feature `async`.
+    #[cfg(feature = "async")]
+    pub async fn context_envvars_async(&self) -> Result<HashMap<String, String>, Error> {
+        crate::asynchronous::get(self.clone(), None, Vec::new(), LaunchMode::Plain)
+            .await
+            .map(|(envvars, _)| decoder::to_unicode(envvars))
+    }
+
+    /// Asynchronous version of `load`. Available with feature `async`. Dropping of
+    /// the returned future kills the spawned shell.
+    #[cfg(feature = "async")]
+    pub async fn load_async(&self, profile: &Profile) -> Result<HashMap<String, String>, Error> {
+        crate::asynchronous::get(
+            self.clone(),
+            Some(profile.path.clone()),
+            profile.arguments(profile.mode),
+            profile.mode,
+        )
+        .await
+        .map(|(envvars, _)| decoder::to_unicode(envvars))
+    }
+
+    /// Removes the extractor of session from the disk. See `envvars::cleanup` for
+    /// details.
+    pub fn cleanup(&self) -> Result<(), io::Error> {
+        lock(&self.inner.extractor).cleanup()
+    }
+
+    /// Delivers the extractor and returns a guard, which removes the extractor as soon
+    /// as the last guard of session is dropped. See `envvars::init` for details.
+    pub fn guard(&self) -> Result<Guard, Error> {
+        let executable = lock(&self.inner.extractor).prepare()?;
+        self.inner.guards.fetch_add(1, Ordering::SeqCst);
+        Ok(Guard {
+            session: self.clone(),
+            executable: Some(executable),
+        })
+    }
+
+    /// Removes extractors of other versions. See `envvars::cleanup_stale` for details.
+    pub fn cleanup_stale(&self) -> Result<Vec<PathBuf>, io::Error> {
+        lock(&self.inner.extractor).cleanup_stale()
+    }
+
+    /// Used only for testing to get prepared extractor
+    #[cfg(test)]
+    pub(crate) fn executable(&self) -> Result<Executable, Error> {
+        lock(&self.inner.extractor).prepare()
+    }
+
+    /// Used only for testing to get location of extractor
+    #[cfg(test)]
+    pub(crate) fn location(&self) -> Option<PathBuf> {
+        lock(&self.inner.extractor).location()
+    }
+
+    /// Used only for testing to count hash checking
+    #[cfg(test)]
+    pub(crate) fn hashed(&self) -> usize {
+        lock(&self.inner.extractor).hashed
+    }
+
+    /// Used only for testing to confirm status of hash checking
+    #[cfg(test)]
+    pub(crate) fn invalid_hash(&self) -> bool {
+        lock(&self.inner.extractor).invalid_hash
+    }
+}
+
+/// Guard of the extractor. While the guard is alive, the extractor is kept on the disk
+/// and cannot be removed by other processes. As soon as the last guard of session is
+/// dropped, the extractor is removed, if it isn't used by another process (a running
+/// loading or a guard in another process). See `envvars::init` for details.
+#[must_use = "the extractor is removed as soon as the guard is dropped"]
+pub struct Guard {
+    session: Envvars,
+    /// Keeps the extractor locked as used
+    executable: Option<Executable>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        // Own lock of usage should be released before removing
+        drop(self.executable.take());
+        if self.session.inner.guards.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        match self.session.cleanup() {
+            Ok(()) => log::debug!("Extractor is removed"),
+            Err(err) if err.kind() == io::ErrorKind::ResourceBusy => {
+                log::debug!("Extractor isn't removed: {err}");
+            }
+            Err(err) => log::warn!("Fail to remove extractor: {err}"),
+        }
+    }
+}
+
+impl Default for Envvars {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test() {
+        let _shared = crate::SHARED.lock().unwrap_or_else(|e| e.into_inner());
+        let session = Envvars::new();
+        let poisoning = session.clone();
+        let _ = thread::spawn(move || {
+            let _extractor = poisoning.inner.extractor.lock();
+            panic!("Poisoning of extractor's lock");
+        })
+        .join();
+        assert!(session.inner.extractor.is_poisoned());
+        let vars = session
+            .context_envvars()
+            .expect("Envvars should be extracted with poisoned lock");
+        assert!(!vars.is_empty());
+    }
+
+    #[test]
+    fn guard() {
+        let dir = crate::fixture::TempDir::new("guard");
+        let session = dir.session();
+        let first = session.guard().expect("Extractor should be delivered");
+        let second = session.guard().expect("Extractor should be delivered");
+        let location = session.location().expect("Extractor should be delivered");
+        // Another session (like another process) is using the same extractor
+        let another = dir.session();
+        let third = another.guard().expect("Extractor should be delivered");
+        drop(first);
+        assert!(location.exists());
+        drop(second);
+        assert!(location.exists());
+        drop(third);
+        assert!(!location.exists());
+    }
+}